@@ -0,0 +1,122 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252, X_USER_DEFINED};
+use regex::{bytes, Regex};
+use url::Url;
+
+// Where browsers look for a `<meta>` declaring the charset
+const PRESCAN_LEN: usize = 1024;
+
+lazy_static! {
+    static ref META_TAG: bytes::Regex = bytes::Regex::new(r"(?is)<meta\s[^>]*>").unwrap();
+    static ref META_CHARSET: bytes::Regex = bytes::Regex::new(
+        r#"(?i)charset\s*=\s*["']?\s*([a-z0-9_.:-]+)"#).unwrap();
+    static ref DECLARATION: Regex = Regex::new(
+        r#"(?i)(<meta\s[^>]*charset\s*=\s*["']?\s*)[a-z0-9_.:-]+"#).unwrap();
+}
+
+/// Page `content` as UTF-8, decoded with the encoding of its byte order mark, else of
+/// its `Content-Type` header, else of its `<meta>` tags, else the one it looks like.
+/// Charset declarations in the page are updated to match.
+pub fn decode(content: &[u8], content_type: Option<&str>, url: &Url) -> String {
+    let (encoding, source) = match Encoding::for_bom(content) {
+        Some((encoding, _)) => (encoding, "byte order mark"),
+        None => match content_type.and_then(header_encoding) {
+            Some(encoding) => (encoding, "HTTP header"),
+            None => match meta_encoding(content) {
+                Some(encoding) => (encoding, "meta tag"),
+                None => (sniff(content, url), "content"),
+            },
+        },
+    };
+    if encoding != UTF_8 {
+        info!("Converting {} from {}, found in its {}", url, encoding.name(), source);
+    }
+
+    // The byte order mark is dropped as well
+    let (text, _, malformed) = encoding.decode(content);
+    if malformed {
+        debug!("Invalid {} characters replaced in {}", encoding.name(), url);
+    }
+
+    let head_len = text.char_indices().nth(PRESCAN_LEN).map_or(text.len(), |(i, _)| i);
+    let head = DECLARATION.replace_all(&text[..head_len], "${1}utf-8");
+
+    format!("{}{}", head, &text[head_len..])
+}
+
+// Encoding named by the `charset` parameter of a `Content-Type` value
+fn header_encoding(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';')
+        .find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            if name.trim().eq_ignore_ascii_case("charset") { Some(value.trim().trim_matches(|c| c == '"' || c == '\'')) } else { None }
+        })
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+}
+
+// Encoding declared by `<meta charset>` or `<meta http-equiv="Content-Type">` at the start
+fn meta_encoding(content: &[u8]) -> Option<&'static Encoding> {
+    let head = &content[..content.len().min(PRESCAN_LEN)];
+    let encoding = META_TAG.find_iter(head)
+        .find_map(|tag| META_CHARSET.captures(tag.as_bytes()))
+        .and_then(|charset| Encoding::for_label(&charset[1]))?;
+
+    // A page read as bytes can't be UTF-16, browsers take these declarations this way
+    Some(match encoding {
+        encoding if encoding == UTF_16BE || encoding == UTF_16LE => UTF_8,
+        encoding if encoding == X_USER_DEFINED => WINDOWS_1252,
+        encoding => encoding,
+    })
+}
+
+// Most likely encoding of undeclared `content`, helped by the top-level domain of `url`
+fn sniff(content: &[u8], url: &Url) -> &'static Encoding {
+    let tld = url.host_str()
+        .and_then(|host| host.rsplit('.').next())
+        .filter(|tld| tld.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(str::to_lowercase);
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(content, true);
+    detector.guess(tld.as_deref().map(str::as_bytes), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(content: &[u8], content_type: Option<&str>, url: &str) -> String {
+        decode(content, content_type, &Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn decodes_declared_encodings() {
+        let latin1 = b"<html><head><meta charset=\"iso-8859-1\"></head><body>Caf\xe9 \xe0 la cr\xe8me</body></html>";
+        let text = decoded(latin1, None, "https://example.fr/");
+        assert!(text.contains("Café à la crème"), "{}", text);
+        assert!(text.contains("<meta charset=\"utf-8\">"), "{}", text);  // Declares what it now is
+
+        // The header wins over the page, the byte order mark over both
+        let page = "<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1252\"><p>Привет</p>";
+        let (koi8, _, _) = encoding_rs::KOI8_R.encode(page);
+        assert!(decoded(&koi8, Some("text/html; charset=\"KOI8-R\""), "https://example.ru/").contains("Привет"));
+        let mut bom = vec![0xef, 0xbb, 0xbf];
+        bom.extend_from_slice("<meta charset=\"iso-8859-1\"><p>Ünïcödé</p>".as_bytes());
+        let text = decoded(&bom, Some("text/html; charset=iso-8859-1"), "https://example.com/");
+        assert!(text.starts_with("<meta") && text.contains("Ünïcödé"), "{}", text);
+
+        // Bytes can't be UTF-16, whatever the page says
+        let text = decoded("<meta charset=\"utf-16\"><p>déjà</p>".as_bytes(), None, "https://example.com/");
+        assert!(text.contains("déjà"), "{}", text);
+    }
+
+    #[test]
+    fn guesses_undeclared_encodings() {
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("<p>日本語のテキストです。これは記事の本文です。</p>");
+        assert!(decoded(&sjis, Some("text/html"), "https://example.jp/").contains("日本語のテキスト"));
+
+        let text = decoded(b"<p>na\xefve fa\xe7ade r\xe9sum\xe9</p>", None, "https://example.com/");
+        assert!(text.contains("naïve façade résumé"), "{}", text);
+        assert_eq!(decoded("<p>plain ✓</p>".as_bytes(), None, "https://example.com/"), "<p>plain ✓</p>");
+    }
+}
@@ -1,65 +1,72 @@
-use std::process::Command;
+use std::process::{Command, Output};
 use std::collections::HashMap;
+use std::path::Path;
+
+use crate::errors::*;
 
 pub struct CalibreCmd {}
 
 impl CalibreCmd {
-    pub fn convert(file: &str, to_ext: &str) -> String {
-        // TODO: use format, more elegance
-        let convert_arg = format!(
-            r#"ebook-convert "{file}" .{ext}"#,
-            file = file,
-            ext = to_ext,
-        );
-
-        println!("***** conversion *****");
-        let output = if cfg!(target_os = "windows") {
-            Command::new("cmd").arg("/C").arg(&convert_arg).output()
-            .expect("Windows failed to execute send cmd")
-        } else {
-            Command::new("sh").arg("-c").arg(&convert_arg).output()
-            .expect("Linux failed to execute send cmd")
-        };
-
-        String::from_utf8_lossy(&output.stdout).to_string()
+    /// `ebook-convert` invocation turning `file` into `output`, the format following its extension.
+    /// The program is spawned directly (no shell) so that the child can be killed on cancel.
+    pub fn convert(file: &Path, output: &Path) -> Command {
+        let mut cmd = Command::new("ebook-convert");
+        cmd.arg(file).arg(output);
+
+        cmd
     }
 
-    pub fn send(filename: &str, cfg: HashMap<String, String>) -> String {
-        println!("***** sending... *****");
-
-        // Calibre Send smtp commands
-        let smtp_arg = format!(
-            r#"{cmd} {a} {s} {r} {port} {u} {p} {mail} {tomail} """#,
-            cmd = "calibre-smtp",
-            a = format!(r#"-a "{}.mobi""#, "filename"),  // Attachment must be mobi
-            s = format!(r#"-s "{}""#, "filename"),  // Subject can be epub/azw3/other
-            r = format!(r#"-r "{}""#, cfg.get("smtp").unwrap()),
-            port = format!(r#"--port {}"#, cfg.get("port").unwrap()),
-            u = format!(r#"-u "{}""#, cfg.get("username").unwrap()),
-            p = format!(r#"-p "{}""#, cfg.get("password").unwrap()),
-            mail = format!(r#""{}""#, cfg.get("from_mail").unwrap()),
-            tomail = format!(r#""{}""#, cfg.get("to_mail").unwrap()),
-        );
-
-        let this_smtp_arg = smtp_arg.replace("filename", &filename);
-        // println!("{}\n", this_smtp_arg);
-
-        // Launch command
-        let output = if cfg!(target_os = "windows") {
-            Command::new("cmd").arg("/C").arg(&this_smtp_arg).output()
-            .expect("Windows failed to execute send cmd")
-        } else {
-            Command::new("sh").arg("-c").arg(&this_smtp_arg).output()
-            .expect("Linux failed to execute send cmd")
-        };
-
-        // Shell output
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        println!("{}", stdout);
-        stdout
+    /// `calibre-smtp` invocation mailing `attachment` with the account in `cfg`.
+    pub fn send(attachment: &Path, cfg: &HashMap<String, String>) -> Command {
+        let subject = attachment.file_stem().unwrap_or_default().to_string_lossy();
+
+        let mut cmd = Command::new("calibre-smtp");
+        cmd.arg("-a").arg(attachment)
+            .arg("-s").arg(subject.as_ref())
+            .arg("-r").arg(cfg.get("smtp").unwrap())
+            .arg("--port").arg(cfg.get("port").unwrap())
+            .arg("-u").arg(cfg.get("username").unwrap())
+            .arg("-p").arg(cfg.get("password").unwrap())
+            .arg(cfg.get("from_mail").unwrap())
+            .arg(cfg.get("to_mail").unwrap())
+            .arg("");  // Empty mail body
+
+        cmd
     }
 }  // CalibreCmd
 
+pub struct ImageMagickCmd {}
+
+impl ImageMagickCmd {
+    /// `convert` invocation turning the first frame of the image on its standard input,
+    /// in the ImageMagick `format` (e.g. `webp`), into PNG on its standard output.
+    /// Only the coder of `format` reads the input, never a file named in it.
+    pub fn to_png(format: &str) -> Command {
+        let mut cmd = Command::new("convert");
+        cmd.arg(format!("{}:-[0]", format)).arg("png:-");
+
+        cmd
+    }
+}  // ImageMagickCmd
+
+pub struct Sqlite3Cmd {}
+
+impl Sqlite3Cmd {
+    /// `sqlite3` invocation running `query` on the database at `db`, printing fields
+    /// and rows separated as given.
+    pub fn query(db: &Path, query: &str, field_separator: &str, row_separator: &str) -> Command {
+        let mut cmd = Command::new("sqlite3");
+        cmd.arg("-readonly")
+            .arg("-separator").arg(field_separator)
+            .arg("-newline").arg(row_separator)
+            .arg(db)
+            .arg(query);
+
+        cmd
+    }
+}  // Sqlite3Cmd
+
+#[derive(Clone, Copy)]
 pub enum ReadabiliPyParser {
     Python,
     Mozilla,
@@ -76,30 +83,27 @@ impl ReadabiliPyCmd {
         }
     }
 
-    pub fn json_from_file(&self, html_fpath: String, json_fpath: String) -> String {
-
-        let parser_arg = match self.parser {
-            ReadabiliPyParser::Python => { "-p" },
-            ReadabiliPyParser::Mozilla => { "" },
-        };
-
-        let arg = format!(
-            r#"readabilipy {parser} -i {in} -o {out}"#,
-            parser = parser_arg,
-            in = html_fpath,
-            out = json_fpath,
-        );
-
-        // Launch command. TODO: Add to trait for all commands!
-        let output = if cfg!(target_os = "windows") {
-            Command::new("cmd").arg("/C").arg(&arg).output()
-            .expect("Windows failed to execute send cmd")
-        } else {
-            Command::new("sh").arg("-c").arg(&arg).output()
-            .expect("Linux failed to execute send cmd")
-        };
-
-        // Shell output
-        String::from_utf8_lossy(&output.stdout).to_string()
+    pub fn json_from_file(&self, html_fpath: &Path, json_fpath: &Path) -> Result<Output> {
+        let mut cmd = Command::new("readabilipy");
+
+        if let ReadabiliPyParser::Python = self.parser {
+            cmd.arg("-p");
+        }
+
+        let output = cmd.arg("-i").arg(html_fpath).arg("-o").arg(json_fpath).output()
+            .chain_err(|| "Couldn't run readabilipy, is it installed?")?;
+
+        check_output("readabilipy", output)
     }
 }
+
+/// Turn a failed exit status into an `ErrorKind::Tool` error carrying stderr.
+pub fn check_output(program: &str, output: Output) -> Result<Output> {
+    if output.status.success() {
+        return Ok(output)
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let stderr = if stderr.is_empty() { format!("{}", output.status) } else { stderr };
+    Err(ErrorKind::Tool(program.into(), stderr).into())
+}
@@ -1,10 +1,14 @@
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use std::result::Result;
+use std::path::{Path, PathBuf};
+
+use crate::errors::*;
+use crate::logging;
 
 // Config file serialization
 // PultConf is for sending and converting
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]  // Fields missing from older config files take default values
 pub struct PultConf {
     pub del_sent: String,
     pub to_ext: String,
@@ -14,6 +18,23 @@ pub struct PultConf {
     pub password: String,
     pub from_mail: String,
     pub to_mail: String,
+    pub max_jobs: String,  // Number of concurrent conversions
+    pub http_enabled: String,  // Web page and API to queue from other devices
+    pub http_address: String,
+    pub http_token: String,
+    pub send_articles: String,  // Send downloaded articles instead of just saving them
+    pub output_dir: String,  // Where articles are saved, the Downloads folder if empty
+    pub name_template: String,  // Names of articles and converted files, see `naming::file_stem`
+    pub extractors: String,  // Article extractors to try in order, see `extract::best_article`
+    pub site_extractors: String,  // Same, per domain: `example.com=raw; news.org=readabilipy`
+    pub device: String,  // Reading device to optimize article images for, none if empty
+    pub user_agent: String,  // Sent when downloading articles, Kindle-pult's own if empty
+    pub proxy: String,  // E.g. `http://proxy:3128` or `socks5h://127.0.0.1:9050` for Tor
+    pub extra_headers: String,  // Sent with every download: `Name: value; Other: value`
+    pub ca_file: String,  // PEM certificates to trust besides the system ones
+    pub insecure_hosts: String,  // Hosts whose certificates aren't checked, e.g. self-signed: `a.lan, b.lan`
+    pub cookies_file: String,  // Browser cookies for downloads: `cookies.txt`, Firefox `cookies.sqlite` or profile
+    pub block_private_addresses: String,  // Refuse downloads from this machine and the local network
 }
 
 /// `PultConf` implements `Default`
@@ -28,6 +49,23 @@ impl Default for PultConf {
             password: "your-password".into(),
             from_mail: "user.name@gmail.com".into(),
             to_mail: "ebook-mail@kindle.com".into(),
+            max_jobs: "2".into(),
+            http_enabled: "false".into(),
+            http_address: "127.0.0.1:8642".into(),
+            http_token: "".into(),
+            send_articles: "false".into(),
+            output_dir: "".into(),
+            name_template: "{title}".into(),
+            extractors: "native,readabilipy".into(),
+            site_extractors: "".into(),
+            device: "".into(),
+            user_agent: "".into(),
+            proxy: "".into(),
+            extra_headers: "".into(),
+            ca_file: "".into(),
+            insecure_hosts: "".into(),
+            cookies_file: "".into(),
+            block_private_addresses: "false".into(),
         }
     }
 }
@@ -43,28 +81,113 @@ impl PultConf {
         values.insert(String::from("password"), String::from(&self.password));
         values.insert(String::from("from_mail"), String::from(&self.from_mail));
         values.insert(String::from("to_mail"), String::from(&self.to_mail));
+        values.insert(String::from("max_jobs"), String::from(&self.max_jobs));
+        values.insert(String::from("http_enabled"), String::from(&self.http_enabled));
+        values.insert(String::from("http_address"), String::from(&self.http_address));
+        values.insert(String::from("http_token"), String::from(&self.http_token));
+        values.insert(String::from("send_articles"), String::from(&self.send_articles));
+        values.insert(String::from("output_dir"), String::from(&self.output_dir));
+        values.insert(String::from("name_template"), String::from(&self.name_template));
+        values.insert(String::from("extractors"), String::from(&self.extractors));
+        values.insert(String::from("site_extractors"), String::from(&self.site_extractors));
+        values.insert(String::from("device"), String::from(&self.device));
+        values.insert(String::from("user_agent"), String::from(&self.user_agent));
+        values.insert(String::from("proxy"), String::from(&self.proxy));
+        values.insert(String::from("extra_headers"), String::from(&self.extra_headers));
+        values.insert(String::from("ca_file"), String::from(&self.ca_file));
+        values.insert(String::from("insecure_hosts"), String::from(&self.insecure_hosts));
+        values.insert(String::from("cookies_file"), String::from(&self.cookies_file));
+        values.insert(String::from("block_private_addresses"), String::from(&self.block_private_addresses));
 
         values
     }
 
-    pub fn reload() -> HashMap<String, String> {
+    pub fn reload() -> Result<HashMap<String, String>> {
         // Load config file info
-        let confy_loaded: Result<PultConf, confy::ConfyError> = confy::load("kindle-pult");
+        let confy_loaded: std::result::Result<PultConf, confy::ConfyError> = confy::load("kindle-pult");
 
         // Reset to default if some error occurs
-        let conf_hashmap: HashMap<String, String> = match confy_loaded {
+        match confy_loaded {
             Ok(c) => {
-                c.dump_to_hashmap()
+                c.add_secrets();
+                Ok(c.dump_to_hashmap())
             },
             Err(e) => {
-                println!("{:?}", e);
-                println!("Replacing with default config values and dumping.");
-                let _ = confy::store("kindle-pult", PultConf::default());
-                let default_c: PultConf = confy::load("kindle-pult").unwrap();
-                default_c.dump_to_hashmap()
+                warn!("Couldn't load config: {}", e);
+                warn!("Replacing with default config values and dumping.");
+                PultConf::default().store()?;
+                Ok(PultConf::default().dump_to_hashmap())
             },
+        }
+    }
+
+    pub fn store(self) -> Result<()> {
+        self.add_secrets();
+        confy::store("kindle-pult", self).chain_err(|| ErrorKind::Config("save".into()))
+    }
+
+    /// Load the named profile, `profiles/<name>.toml` in the config directory.
+    /// Profiles have the same fields as the main config file.
+    pub fn reload_profile(name: &str) -> Result<HashMap<String, String>> {
+        let invalid = name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']);
+        let path = config_dir().map(|dir| dir.join("profiles").join(format!("{}.toml", name)));
+
+        let path = match path {
+            Some(path) if !invalid && path.is_file() => path,
+            _ => bail!(ErrorKind::Config(format!("find the '{}' profile of", name))),
         };
 
-        conf_hashmap
+        load_profile(&path).chain_err(|| ErrorKind::Config(format!("load the '{}' profile of", name)))
+    }
+
+    // Keep the passwords and tokens of this conf out of the logs
+    fn add_secrets(&self) {
+        logging::add_secret(&self.password);
+        logging::add_secret(&self.http_token);
+    }
+
+    /// Default values, as the same map `reload` returns.
+    pub fn defaults() -> HashMap<String, String> {
+        PultConf::default().dump_to_hashmap()
+    }
+}
+
+// Profile file at `path`, its secrets kept out of the logs
+fn load_profile(path: &Path) -> Result<HashMap<String, String>> {
+    let conf: PultConf = confy::load_path(path)?;
+    conf.add_secrets();
+    Ok(conf.dump_to_hashmap())
+}
+
+/// Folder for downloaded articles: `output_dir`, or the user's Downloads folder if unset.
+pub fn output_dir(cfg: &HashMap<String, String>) -> PathBuf {
+    match cfg.get("output_dir").map(String::as_str) {
+        Some(dir) if !dir.trim().is_empty() => PathBuf::from(dir.trim()),
+        _ => directories::UserDirs::new()
+            .map(|dirs| dirs.download_dir().unwrap_or_else(|| dirs.home_dir()).to_path_buf())
+            .unwrap_or_else(|| PathBuf::from(".")),
+    }
+}
+
+/// Directory holding `kindle-pult.toml`, as chosen by confy.
+pub fn config_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("rs", "", "kindle-pult").map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn profiles_hide_their_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.toml");
+        fs::write(&path, "password = \"profile-pass\"\nhttp_token = \"profile-token\"\nto_ext = \"epub\"\n").unwrap();
+
+        let cfg = load_profile(&path).unwrap();
+        assert_eq!(cfg["to_ext"], "epub");
+        assert_eq!(cfg["smtp"], PultConf::default().smtp);  // Missing fields take defaults
+        assert_eq!(logging::redact("sent with profile-pass by profile-token"), "sent with <redacted> by <redacted>");
     }
 }
@@ -0,0 +1,197 @@
+use std::fs;
+use std::collections::HashMap;
+use std::path::Path;
+
+use tempfile::Builder;
+use url::Url;
+
+use crate::cmd::{self, Sqlite3Cmd};
+use crate::errors::*;
+
+// Separators of the `sqlite3` output, never found in cookies
+const FIELD_SEPARATOR: &str = "\u{1f}";
+const ROW_SEPARATOR: &str = "\u{1e}";
+
+const FIREFOX_QUERY: &str = "SELECT host, path, isSecure, expiry, name, value FROM moz_cookies";
+
+struct Cookie {
+    domain: String,  // Without the leading dot
+    subdomains: bool,  // Sent to subdomains too
+    path: String,
+    secure: bool,  // HTTPS only
+    expires: i64,  // Unix time in seconds, 0 for session cookies
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    fn matches(&self, url: &Url, now: i64) -> bool {
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        let domain_matches = host == self.domain || (self.subdomains && host.ends_with(&format!(".{}", self.domain)));
+        let path = url.path();
+        let path_matches = path.starts_with(&self.path)
+            && (self.path.ends_with('/') || path.len() == self.path.len() || path[self.path.len()..].starts_with('/'));
+
+        domain_matches && path_matches && (!self.secure || url.scheme() == "https") && (self.expires == 0 || self.expires > now)
+    }
+}
+
+/// Cookies imported from a browser, sent to the sites they belong to.
+#[derive(Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// Cookies of the `cookies_file` in `cfg`: a Netscape `cookies.txt`, a Firefox
+    /// `cookies.sqlite` or the profile folder holding it. Empty if unset or unreadable.
+    pub fn load(cfg: &HashMap<String, String>) -> Self {
+        let file = cfg.get("cookies_file").unwrap().trim();
+        if file.is_empty() {
+            return CookieJar::default()
+        }
+
+        let path = Path::new(file);
+        let path = if path.is_dir() { path.join("cookies.sqlite") } else { path.to_path_buf() };
+        let cookies = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("sqlite") => firefox_cookies(&path),
+            _ => fs::read_to_string(&path).map_err(Error::from).map(|text| netscape_cookies(&text)),
+        };
+
+        match cookies {
+            Ok(cookies) => {
+                debug!("{} cookies imported from {}", cookies.len(), path.display());
+                CookieJar { cookies }
+            },
+            Err(e) => {
+                warn!("Couldn't import cookies from {}: {}", path.display(), e);
+                CookieJar::default()
+            },
+        }
+    }
+
+    /// Value of the `Cookie` header for a request to `url`, if any cookie belongs to it.
+    pub fn header_for(&self, url: &Url) -> Option<String> {
+        let now = chrono::Utc::now().timestamp();
+        let pairs: Vec<String> = self.cookies.iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+
+        if pairs.is_empty() { None } else { Some(pairs.join("; ")) }
+    }
+}
+
+// Lines of `domain  subdomains  path  secure  expiry  name  value`, tab separated
+fn netscape_cookies(text: &str) -> Vec<Cookie> {
+    text.lines()
+        .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line))  // Cookies too, not comments
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+            match fields.as_slice() {
+                [domain, subdomains, path, secure, expires, name, value] => Some(Cookie {
+                    domain: domain.trim_start_matches('.').to_lowercase(),
+                    subdomains: subdomains.eq_ignore_ascii_case("TRUE") || domain.starts_with('.'),
+                    path: path.to_string(),
+                    secure: secure.eq_ignore_ascii_case("TRUE"),
+                    expires: expires.parse().unwrap_or(0),
+                    name: name.to_string(),
+                    value: value.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Firefox keeps its database locked while running, a copy is read instead
+fn firefox_cookies(path: &Path) -> Result<Vec<Cookie>> {
+    let tmp_dir = Builder::new().prefix("kindle-pult_").tempdir()?;
+    let copy = tmp_dir.path().join("cookies.sqlite");
+    fs::copy(path, &copy)?;
+    // Recent changes may still be in the write-ahead log
+    let wal = path.with_file_name("cookies.sqlite-wal");
+    if wal.is_file() {
+        fs::copy(&wal, tmp_dir.path().join("cookies.sqlite-wal"))?;
+    }
+
+    let output = Sqlite3Cmd::query(&copy, FIREFOX_QUERY, FIELD_SEPARATOR, ROW_SEPARATOR).output()
+        .chain_err(|| "Couldn't run sqlite3, is it installed?")?;
+    let output = cmd::check_output("sqlite3", output)?;
+
+    let cookies = String::from_utf8_lossy(&output.stdout)
+        .split(ROW_SEPARATOR)
+        .filter_map(|row| {
+            let fields: Vec<&str> = row.split(FIELD_SEPARATOR).collect();
+            match fields.as_slice() {
+                [host, path, secure, expires, name, value] => {
+                    let expires: i64 = expires.trim().parse().unwrap_or(0);
+                    Some(Cookie {
+                        domain: host.trim().trim_start_matches('.').to_lowercase(),
+                        subdomains: host.trim().starts_with('.'),
+                        path: path.to_string(),
+                        secure: secure.trim() == "1",
+                        // Newer Firefox versions count milliseconds
+                        expires: if expires > 100_000_000_000 { expires / 1000 } else { expires },
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })
+                },
+                _ => None,
+            }
+        })
+        .collect();
+
+    Ok(cookies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PultConf;
+
+    const COOKIES_TXT: &str = "# Netscape HTTP Cookie File\r\n\
+        .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\r\n\
+        #HttpOnly_news.example.com\tFALSE\t/articles\tTRUE\t4102444800\tlogin\tsecret\r\n\
+        old.example.com\tFALSE\t/\tFALSE\t946684800\texpired\tgone\r\n\
+        broken line without tabs\r\n\
+        \r\n\
+        other.org\tFALSE\t/\tFALSE\t0\tother\tvalue\r\n";
+
+    fn header(jar: &CookieJar, url: &str) -> Option<String> {
+        jar.header_for(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn sends_cookies_to_their_sites_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("cookies.txt");
+        fs::write(&file, COOKIES_TXT).unwrap();
+        let mut cfg = PultConf::defaults();
+        cfg.insert("cookies_file".into(), file.to_string_lossy().into());
+        let jar = CookieJar::load(&cfg);
+        assert_eq!(jar.cookies.len(), 4);
+
+        assert_eq!(header(&jar, "https://example.com/").as_deref(), Some("session=abc"));
+        assert_eq!(header(&jar, "http://www.example.com/a").as_deref(), Some("session=abc"));
+        assert_eq!(header(&jar, "https://news.example.com/articles/1").as_deref(), Some("session=abc; login=secret"));
+        assert_eq!(header(&jar, "https://news.example.com/articles").as_deref(), Some("session=abc; login=secret"));
+        // Not over HTTP, not outside its path, not to subdomains
+        assert_eq!(header(&jar, "http://news.example.com/articles/1").as_deref(), Some("session=abc"));
+        assert_eq!(header(&jar, "https://news.example.com/articles-old").as_deref(), Some("session=abc"));
+        assert_eq!(header(&jar, "https://a.news.example.com/articles/1").as_deref(), Some("session=abc"));
+        // Expired, or for other sites
+        assert_eq!(header(&jar, "https://old.example.com/").as_deref(), Some("session=abc"));
+        assert_eq!(header(&jar, "https://notexample.com/"), None);
+        assert_eq!(header(&jar, "https://other.org.evil.com/"), None);
+    }
+
+    #[test]
+    fn ignores_missing_files() {
+        let mut cfg = PultConf::defaults();
+        assert!(CookieJar::load(&cfg).cookies.is_empty());
+        cfg.insert("cookies_file".into(), "/nonexistent/cookies.txt".into());
+        assert!(CookieJar::load(&cfg).cookies.is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+use std::env;
+use std::io;
+use std::thread;
+use std::path::PathBuf;
+use std::convert::TryInto;
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use zbus::{dbus_interface, fdo, Connection, ObjectServer};
+
+use crate::config::PultConf;
+use crate::jobs::{JobEvent, JobId, JobPool, JobSource};
+use crate::remote;
+
+pub const BUS_NAME: &str = "it.zwitterio.KindlePult";
+pub const OBJECT_PATH: &str = "/it/zwitterio/KindlePult";
+pub const INTERFACE: &str = "it.zwitterio.KindlePult1";
+
+// Bus to use instead of the session one, e.g. a private dbus-daemon for testing
+const ADDRESS_VAR: &str = "KINDLE_PULT_DBUS_ADDRESS";
+// Longest wait for calls before pending signals go out
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Service {
+    jobs: JobPool,
+    cfg: HashMap<String, String>,
+}
+
+impl Service {
+    fn cfg_for(&self, profile: &str) -> fdo::Result<HashMap<String, String>> {
+        if profile.is_empty() {
+            return Ok(self.cfg.clone())
+        }
+
+        PultConf::reload_profile(profile).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))
+    }
+}
+
+type JobTuple = (u64, String, String, u32, u32);
+
+/// `(id, source, state, done, total)`, state being one of `JobState::name`.
+/// `done` and `total` count the files of the current state, e.g. article images, 0 if unknown.
+fn job_tuple(event: &JobEvent) -> JobTuple {
+    let source = match &event.source {
+        JobSource::File(file) => file.to_string_lossy().to_string(),
+        JobSource::Url(url) => url.clone(),
+    };
+    let (done, total) = event.progress.unwrap_or_default();
+
+    (event.id as u64, source, event.state.name().into(), done as u32, total as u32)
+}
+
+#[dbus_interface(name = "it.zwitterio.KindlePult1")]
+impl Service {
+    /// Convert and send the file at the absolute `path`.
+    /// An empty `profile` selects the default configuration.
+    fn queue_file(&self, path: &str, profile: &str) -> fdo::Result<u64> {
+        let file = PathBuf::from(path);
+        if !file.is_absolute() {
+            return Err(fdo::Error::InvalidArgs(format!("'{}' is not an absolute path", path)))
+        }
+        if !file.is_file() {
+            return Err(fdo::Error::FileNotFound(path.into()))
+        }
+
+        let cfg = self.cfg_for(profile)?;
+        Ok(self.jobs.submit(JobSource::File(file), cfg) as u64)
+    }
+
+    /// Download `url` as an article.
+    fn queue_url(&self, url: &str, profile: &str) -> fdo::Result<u64> {
+        if !remote::is_web_url(url) {
+            return Err(fdo::Error::InvalidArgs(format!("'{}' is not a web page", url)))
+        }
+
+        let cfg = self.cfg_for(profile)?;
+        Ok(self.jobs.submit(JobSource::Url(url.into()), cfg) as u64)
+    }
+
+    /// Every job of this session, oldest first.
+    fn list_jobs(&self) -> Vec<JobTuple> {
+        self.jobs.list().iter().map(job_tuple).collect()
+    }
+
+    /// Returns `false` if the job doesn't exist or already finished.
+    fn cancel_job(&self, id: u64) -> bool {
+        self.jobs.cancel(id as JobId)
+    }
+
+    // Declared for introspection, emitted from the job pool listener
+    #[dbus_interface(signal)]
+    fn job_changed(&self, id: u64, source: &str, state: &str, done: u32, total: u32) -> zbus::Result<()>;
+}
+
+/// Serve the job queue on the session bus, from a thread of its own.
+pub fn spawn(jobs: JobPool, cfg: HashMap<String, String>) {
+    thread::spawn(move || {
+        let address = env::var(ADDRESS_VAR).ok();
+        if let Err(e) = serve(jobs, cfg, address.as_deref()) {
+            warn!("D-Bus service stopped: {}", e);
+        }
+    });
+}
+
+fn serve(jobs: JobPool, cfg: HashMap<String, String>, address: Option<&str>) -> zbus::Result<()> {
+    let connection = match address {
+        Some(address) => Connection::new_for_address(address, true)?,
+        None => Connection::new_session()?,
+    };
+
+    let reply = fdo::DBusProxy::new(&connection)?
+        .request_name(BUS_NAME, fdo::RequestNameFlags::DoNotQueue.into())?;
+    if reply != fdo::RequestNameReply::PrimaryOwner && reply != fdo::RequestNameReply::AlreadyOwner {
+        // Calls would go to the other owner, not to our queue
+        let e = fdo::Error::Failed(format!("{} is owned by another process", BUS_NAME));
+        return Err(e.into())
+    }
+    info!("Serving {} on D-Bus", BUS_NAME);
+
+    // Reading holds the connection, so signals are sent from this thread, between calls
+    let (signal_tx, signal_rx) = mpsc::channel();
+    jobs.connect_event(move |event: &JobEvent| {
+        let _ = signal_tx.send(job_tuple(event));
+    });
+
+    let mut server = ObjectServer::new(&connection);
+    server.at(&OBJECT_PATH.try_into()?, Service { jobs, cfg })?;
+
+    loop {
+        for signal in signal_rx.try_iter() {
+            let _ = connection.emit_signal(None, OBJECT_PATH, INTERFACE, "JobChanged", &signal);
+        }
+        if !readable(&connection, POLL_INTERVAL)? {
+            continue
+        }
+
+        match server.try_handle_next() {
+            Err(zbus::Error::Io(e)) => return Err(zbus::Error::Io(e)),  // Connection lost
+            Err(e) => warn!("D-Bus: {}", e),
+            Ok(_) => (),
+        }
+    }
+}
+
+// Whether a message arrives on `connection` within `timeout`
+fn readable(connection: &Connection, timeout: Duration) -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: connection.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    match unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) } {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+            e => Err(e),
+        },
+        ready => Ok(ready > 0),  // Hang-ups too, reading reports them
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    // Private bus, stopped with the test
+    struct Bus(Child);
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    // `None` without dbus-daemon
+    fn start_bus() -> Option<(Bus, String)> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        Some((Bus(daemon), address.trim().into()))
+    }
+
+    fn call<B, R>(connection: &Connection, method: &str, body: &B) -> zbus::Result<R>
+    where
+        B: serde::Serialize + zbus::zvariant::Type,
+        R: serde::de::DeserializeOwned + zbus::zvariant::Type,
+    {
+        let reply = connection.call_method(Some(BUS_NAME), OBJECT_PATH, Some(INTERFACE), method, body)?;
+        reply.body::<R>().map_err(zbus::Error::from)
+    }
+
+    // `JobChanged` signals, read on a connection of their own
+    fn watch_signals(address: &str) -> mpsc::Receiver<JobTuple> {
+        let connection = Connection::new_for_address(address, true).unwrap();
+        let rule = format!("type='signal',interface='{}',member='JobChanged'", INTERFACE);
+        fdo::DBusProxy::new(&connection).unwrap().add_match(&rule).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(message) = connection.receive_message() {
+                if let Ok(signal) = message.body::<JobTuple>() {
+                    if tx.send(signal).is_err() {
+                        break
+                    }
+                }
+            }
+        });
+        rx
+    }
+
+    fn wait_for(signals: &mpsc::Receiver<JobTuple>, id: u64, state: &str, progress: Option<(u32, u32)>) {
+        let deadline = Instant::now() + TIMEOUT;
+        while let Ok((signal_id, _, signal_state, done, total)) = signals.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            if signal_id == id && signal_state == state && progress.is_none_or(|progress| progress == (done, total)) {
+                return
+            }
+        }
+        panic!("no JobChanged signal for job {} {} {:?}", id, state, progress);
+    }
+
+    // Last state of job `id`
+    fn wait_for_end(signals: &mpsc::Receiver<JobTuple>, id: u64) -> String {
+        let deadline = Instant::now() + TIMEOUT;
+        while let Ok((signal_id, _, state, ..)) = signals.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            if signal_id == id && ["done", "saved", "failed", "cancelled"].contains(&state.as_str()) {
+                return state
+            }
+        }
+        panic!("job {} didn't finish", id);
+    }
+
+    // Web server with an article, whose image is missing
+    fn serve_article() -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr();
+        let page = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/news.html")).unwrap();
+
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let response = match request.url() {
+                    "/article" => tiny_http::Response::from_string(page.as_str())
+                        .with_header("Content-Type: text/html; charset=utf-8".parse::<tiny_http::Header>().unwrap()),
+                    _ => tiny_http::Response::from_string("Not found").with_status_code(404),
+                };
+                let _ = request.respond(response);
+            }
+        });
+        format!("http://{}/article", address)
+    }
+
+    #[test]
+    fn serves_the_job_queue() {
+        let (_bus, address) = match start_bus() {
+            Some(bus) => bus,
+            None => return eprintln!("Skipping the D-Bus service test, dbus-daemon isn't installed"),
+        };
+        env::set_var(ADDRESS_VAR, &address);
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = PultConf::defaults();
+        cfg.insert("output_dir".into(), dir.path().to_string_lossy().into());
+        cfg.insert("send_articles".into(), "false".into());
+        // Nothing can be mailed, should Calibre be installed
+        cfg.insert("smtp".into(), "127.0.0.1".into());
+        cfg.insert("port".into(), "1".into());
+        spawn(JobPool::new(2), cfg);
+
+        let client = Connection::new_for_address(&address, true).unwrap();
+        let deadline = Instant::now() + TIMEOUT;
+        while call::<_, Vec<JobTuple>>(&client, "ListJobs", &()).is_err() {
+            assert!(Instant::now() < deadline, "service didn't start");
+            thread::sleep(Duration::from_millis(50));
+        }
+        let signals = watch_signals(&address);
+
+        // Refused before reaching the queue
+        assert!(call::<_, u64>(&client, "QueueUrl", &("not a page", "")).is_err());
+        assert!(call::<_, u64>(&client, "QueueFile", &("relative.epub", "")).is_err());
+
+        let file = dir.path().join("book.txt");
+        std::fs::write(&file, "Some text").unwrap();
+        let file_id: u64 = call(&client, "QueueFile", &(file.to_str().unwrap(), "")).unwrap();
+        wait_for(&signals, file_id, "queued", None);
+        // Stopped while converting, unless it failed first, e.g. without Calibre
+        call::<_, bool>(&client, "CancelJob", &(file_id,)).unwrap();
+        let end = wait_for_end(&signals, file_id);
+        assert!(end == "cancelled" || end == "failed", "file job {}", end);
+
+        // Images are counted as they're downloaded, or not
+        let article_id: u64 = call(&client, "QueueUrl", &(serve_article().as_str(), "")).unwrap();
+        wait_for(&signals, article_id, "fetching", Some((0, 1)));
+        wait_for(&signals, article_id, "fetching", Some((1, 1)));
+        wait_for(&signals, article_id, "saved", None);
+
+        // A server that never answers, so the download is still running when cancelled
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/article", silent.local_addr().unwrap());
+        let url_id: u64 = call(&client, "QueueUrl", &(url.as_str(), "")).unwrap();
+        wait_for(&signals, url_id, "fetching", None);
+
+        let jobs: Vec<JobTuple> = call(&client, "ListJobs", &()).unwrap();
+        assert!(jobs.iter().any(|(id, source, ..)| *id == file_id && source == file.to_str().unwrap()));
+        assert!(jobs.iter().any(|(id, source, ..)| *id == url_id && *source == url));
+
+        assert!(call::<_, bool>(&client, "CancelJob", &(url_id,)).unwrap());
+        wait_for(&signals, url_id, "cancelled", None);
+        assert!(!call::<_, bool>(&client, "CancelJob", &(url_id,)).unwrap());
+        assert!(!call::<_, bool>(&client, "CancelJob", &(9999u64,)).unwrap());
+    }
+}
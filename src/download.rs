@@ -0,0 +1,690 @@
+use std::fs;
+use std::io::Write;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use futures::future::{self, Either};
+use futures::stream::{self, StreamExt};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect;
+use tokio::runtime::{self, Runtime};
+use tokio::sync::Semaphore;
+use url::{Host, Url};
+
+use crate::charset;
+use crate::cookies::CookieJar;
+use crate::errors::*;
+
+// Downloads running at once, in total and from a single host
+const MAX_DOWNLOADS: usize = 8;
+const MAX_HOST_DOWNLOADS: usize = 3;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+// Longest wait for the response or for more of its content
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// How often waiting downloads check whether their task was cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+// Larger files are refused, no article needs them
+const MAX_PAGE_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
+
+const MAX_REDIRECTS: usize = 5;
+// Characters of a file name before its extension, longer ones are cut
+const MAX_NAME_LEN: usize = 100;
+
+// Attempts after a failure that may be temporary, waiting longer each time
+const RETRIES: u32 = 2;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+const DEFAULT_USER_AGENT: &str = concat!("kindle-pult/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Copy, Clone)]
+pub enum DLFileType {
+    Text,
+    Image,
+}
+
+impl DLFileType {
+    // Whether a file of `mime_type` is what was asked for, servers may not say
+    fn accepts(self, mime_type: &str) -> bool {
+        match self {
+            DLFileType::Text => matches!(mime_type,
+                "text/html" | "application/xhtml+xml" | "text/plain" | "text/xml" | "application/xml"),
+            DLFileType::Image => mime_type.starts_with("image/")
+                || mime_type == "application/octet-stream" || mime_type == "binary/octet-stream",
+        }
+    }
+
+    fn max_size(self) -> u64 {
+        match self {
+            DLFileType::Text => MAX_PAGE_SIZE,
+            DLFileType::Image => MAX_FILE_SIZE,
+        }
+    }
+}
+
+type Progress = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Job downloads are made for, they stop as soon as it's cancelled.
+#[derive(Clone, Default)]
+pub struct Task {
+    cancelled: Arc<AtomicBool>,
+    progress: Option<Progress>,
+}
+
+impl Task {
+    pub fn new(cancelled: Arc<AtomicBool>) -> Self {
+        Self {
+            cancelled,
+            progress: None,
+        }
+    }
+
+    /// Call `f` with the number of files finished so far, downloaded or not, and their total.
+    pub fn on_progress<F: Fn(usize, usize) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.progress = Some(Arc::new(f));
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn progress(&self, done: usize, total: usize) {
+        if let Some(progress) = &self.progress {
+            progress(done, total);
+        }
+    }
+}
+
+pub struct Downloader {
+    path: PathBuf,  // Path where all file are collected;
+    pub file_type: Cell<DLFileType>,  // Mutate type with `.set` and `.get` Cell methods
+    client: reqwest::Client,
+    insecure_client: reqwest::Client,  // For `insecure_hosts` only
+    insecure_hosts: Vec<String>,
+    cookies: CookieJar,
+    block_private: bool,  // Refuse addresses on this machine and the local network
+    task: Task,
+    runtime: Runtime,  // Downloads run on the calling thread, which logs them for its job
+}
+
+impl Downloader {
+    /// Downloader to `path`, with the HTTP settings of `cfg`: user agent, proxy,
+    /// extra headers, certificates, browser cookies and private address blocking.
+    /// Downloads stop once `task` is cancelled.
+    pub fn new(path: PathBuf, file_type: DLFileType, cfg: &HashMap<String, String>, task: Task) -> Result<Self> {
+        let insecure_hosts = cfg.get("insecure_hosts").unwrap()
+            .split(',')
+            .map(|host| host.trim().to_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            path,
+            file_type: Cell::new(file_type),
+            client: http_client(cfg, false)?,
+            insecure_client: http_client(cfg, true)?,
+            insecure_hosts,
+            cookies: CookieJar::load(cfg),
+            block_private: cfg.get("block_private_addresses").unwrap().parse().unwrap_or(false),
+            task,
+            runtime,
+        })
+    }  // new_for_path
+
+    pub fn download_from(&self, target: Url) -> Result<PathBuf> {
+        // Choosing filename
+        let filename = target
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| if name.is_empty() { None } else { Some(name) })
+            .unwrap_or("tmp.bin")
+            .to_string();
+
+        self.download_to(target, &filename)
+    }
+
+    /// Whether the task of the downloads was cancelled, later ones fail right away.
+    pub fn is_cancelled(&self) -> bool {
+        self.task.is_cancelled()
+    }
+
+    /// Download `target` as `filename` in the downloader's folder, made safe and with the
+    /// extension of the type of file received.
+    pub fn download_to(&self, target: Url, filename: &str) -> Result<PathBuf> {
+        self.download_all(vec![(target, filename.to_string())]).pop()
+            .unwrap_or_else(|| bail!("nothing downloaded"))
+    }
+
+    /// Download each of `targets` as its file name, several at once, logging progress.
+    /// Results are in the same order.
+    pub fn download_all(&self, targets: Vec<(Url, String)>) -> Vec<Result<PathBuf>> {
+        let total = targets.len();
+        let done = Cell::new(0);
+        if total > 0 {
+            self.task.progress(0, total);
+        }
+        let mut hosts: HashMap<String, Rc<Semaphore>> = HashMap::new();
+
+        let downloads = targets.into_iter().map(|(target, filename)| {
+            let host = target.host_str().unwrap_or_default().to_string();
+            let host_slots = hosts.entry(host).or_insert_with(|| Rc::new(Semaphore::new(MAX_HOST_DOWNLOADS))).clone();
+            let done = &done;
+
+            async move {
+                let _slot = host_slots.acquire().await;
+                let result = self.fetch(&target, &filename).await;
+
+                done.set(done.get() + 1);
+                self.task.progress(done.get(), total);
+                match &result {
+                    Ok(_) if total > 1 => info!("Downloaded {} of {}, {}", done.get(), total, target),
+                    Ok(_) => (),
+                    Err(e) => debug!("Couldn't download {}: {}", target, e),
+                }
+                result
+            }
+        });
+
+        let all = stream::iter(downloads).buffered(MAX_DOWNLOADS).collect::<Vec<_>>();
+        self.runtime.block_on(all)
+    }
+
+    // Download `target`, retrying on failures that may not happen again
+    async fn fetch(&self, target: &Url, filename: &str) -> Result<PathBuf> {
+        let mut attempt = 0;
+        loop {
+            match self.fetch_once(target, filename).await {
+                Err(e) if attempt < RETRIES && is_transient(&e) => {
+                    attempt += 1;
+                    info!("Retrying {} ({})", target, e);
+                    self.watch(target, tokio::time::sleep(RETRY_DELAY * attempt)).await?;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    async fn fetch_once(&self, target: &Url, filename: &str) -> Result<PathBuf> {
+        if self.is_cancelled() {
+            bail!(ErrorKind::Cancelled)
+        }
+
+        let file_type = self.file_type.get();
+        let mut response = self.request(target).await?;
+
+        let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(String::from);
+        let mime_type = content_type.as_deref().map(mime_type);
+        if let Some(mime_type) = &mime_type {
+            if !file_type.accepts(mime_type) {
+                bail!(ErrorKind::UnexpectedContent(target.to_string(), mime_type.clone()))
+            }
+        }
+
+        let max_size = file_type.max_size();
+        if response.content_length().is_some_and(|length| length > max_size) {
+            bail!(ErrorKind::TooLarge(target.to_string(), max_size))
+        }
+
+        let filename = safe_filename(filename, mime_type.as_deref(), file_type);
+        debug!("file to download: '{:?}'", filename);
+
+        // Locate destination
+        let local_abs_path = self.path.join(&filename);
+        debug!("will be located under: '{:?}'", local_abs_path);
+
+        // Text is decoded as a whole, the charset may need all of it
+        let mut content = Vec::new();
+
+        loop {
+            let chunk = self.watch(target, response.chunk()).await??;
+            let chunk = match chunk {
+                Some(chunk) => chunk,
+                None => break,
+            };
+
+            if (content.len() + chunk.len()) as u64 > max_size {
+                bail!(ErrorKind::TooLarge(target.to_string(), max_size))
+            }
+            content.extend_from_slice(&chunk);
+        }
+
+        // Copy file in destination
+        let mut destination = fs::File::create(&local_abs_path)?;
+        match file_type {
+            DLFileType::Text => {
+                let html_string = charset::decode(&content, content_type.as_deref(), target);
+                destination.write_all(html_string.as_bytes())?;
+            },  // if HTML
+            DLFileType::Image => {
+                destination.write_all(&content)?;
+            }  // else if Image
+        }  // match file type
+
+        Ok(local_abs_path)
+    }
+
+    // Response to `target`, following redirects one by one so each address is checked
+    // and gets its own cookies
+    async fn request(&self, target: &Url) -> Result<reqwest::Response> {
+        let mut url = target.clone();
+
+        for _ in 0..=MAX_REDIRECTS {
+            if url.scheme() != "http" && url.scheme() != "https" {
+                bail!(ErrorKind::InvalidUrl(url.to_string()))
+            }
+            if self.block_private {
+                self.check_address(&url)?;
+            }
+
+            // Make HTTP request for target file
+            let host = url.host_str().unwrap_or_default().to_lowercase();
+            let client = if self.insecure_hosts.contains(&host) { &self.insecure_client } else { &self.client };
+            let mut request = client.get(url.as_str());
+            if let Some(cookies) = self.cookies.header_for(&url) {
+                request = request.header(reqwest::header::COOKIE, cookies);
+            }
+            let response = match self.watch(&url, request.send()).await? {
+                Err(e) if is_blocked(&e) => bail!(ErrorKind::Blocked(url.to_string())),
+                response => response?,
+            };
+
+            let location = Some(&response).filter(|response| response.status().is_redirection())
+                .and_then(|response| response.headers().get(reqwest::header::LOCATION))
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok());
+            match location {
+                Some(location) => {
+                    debug!("'{}' redirects to '{}'", url, location);
+                    url = location;
+                },
+                None => return Ok(response.error_for_status()?),
+            }
+        }
+
+        bail!(ErrorKind::TooManyRedirects(target.to_string()))
+    }
+
+    // Output of `future`, unless the task is cancelled or `READ_TIMEOUT` passes first
+    async fn watch<F: Future>(&self, url: &Url, future: F) -> Result<F::Output> {
+        let cancelled = async {
+            while !self.is_cancelled() {
+                tokio::time::sleep(CANCEL_POLL).await;
+            }
+        };
+        futures::pin_mut!(future, cancelled);
+
+        match tokio::time::timeout(READ_TIMEOUT, future::select(future, cancelled)).await {
+            Ok(Either::Left((output, _))) => Ok(output),
+            Ok(Either::Right(_)) => bail!(ErrorKind::Cancelled),
+            Err(_) => bail!(ErrorKind::Timeout(url.to_string())),
+        }
+    }
+
+    // Refuse `url` if it's on this machine or the local network. Other names are checked by
+    // `PublicResolver` as the client looks them up, unless a proxy does it: only the proxy
+    // knows the addresses it connects to.
+    fn check_address(&self, url: &Url) -> Result<()> {
+        let address: IpAddr = match url.host() {
+            Some(Host::Ipv4(address)) => address.into(),
+            Some(Host::Ipv6(address)) => address.into(),
+            Some(Host::Domain(name)) => {
+                let name = name.trim_end_matches('.').to_lowercase();
+                if name == "localhost" || name.ends_with(".localhost") {
+                    bail!(ErrorKind::Blocked(url.to_string()))
+                }
+                return Ok(())
+            },
+            None => bail!(ErrorKind::InvalidUrl(url.to_string())),
+        };
+
+        if is_private(&address) {
+            bail!(ErrorKind::Blocked(url.to_string()))
+        }
+        Ok(())
+    }
+}
+
+// Looks host names up for the client, failing for those on this machine or the local network.
+// The client connects to the addresses checked here, so a name can't change in between.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(lookup_public(name))
+    }
+}
+
+async fn lookup_public(name: Name) -> std::result::Result<Addrs, Box<dyn StdError + Send + Sync>> {
+    let found: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+    if found.iter().any(|address| is_private(&address.ip())) {
+        return Err(Box::new(BlockedName(name.as_str().into())))
+    }
+    Ok(Box::new(found.into_iter()))
+}
+
+// Lookup refused by `PublicResolver`, found again in the errors of the client
+#[derive(Debug)]
+struct BlockedName(String);
+
+impl fmt::Display for BlockedName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' is on a private network", self.0)
+    }
+}
+
+impl StdError for BlockedName {}
+
+// Whether `error` comes from a lookup `PublicResolver` refused
+fn is_blocked(error: &reqwest::Error) -> bool {
+    let mut source: Option<&(dyn StdError + 'static)> = Some(error);
+    while let Some(error) = source {
+        if error.is::<BlockedName>() {
+            return true
+        }
+        source = error.source();
+    }
+    false
+}
+
+// Client with the HTTP settings of `cfg`, not checking certificates if `insecure`
+fn http_client(cfg: &HashMap<String, String>, insecure: bool) -> Result<reqwest::Client> {
+    let user_agent = cfg.get("user_agent").unwrap().trim();
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .user_agent(if user_agent.is_empty() { DEFAULT_USER_AGENT } else { user_agent })
+        .default_headers(extra_headers(cfg.get("extra_headers").unwrap()))
+        .danger_accept_invalid_certs(insecure)
+        .redirect(redirect::Policy::none());  // Followed by the downloader
+
+    let proxy = cfg.get("proxy").unwrap().trim();
+    if !proxy.is_empty() {
+        builder = builder.proxy(reqwest::Proxy::all(proxy).chain_err(|| format!("Invalid proxy '{}'", proxy))?);
+    } else if cfg.get("block_private_addresses").unwrap().parse().unwrap_or(false) {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+
+    let ca_file = cfg.get("ca_file").unwrap().trim();
+    if !ca_file.is_empty() {
+        let pem = fs::read_to_string(ca_file).chain_err(|| format!("Couldn't read certificates from '{}'", ca_file))?;
+        // A bundle is several certificates one after another
+        for cert in pem.split_inclusive("-----END CERTIFICATE-----").filter(|cert| cert.contains("-----BEGIN")) {
+            let cert = reqwest::Certificate::from_pem(cert.trim().as_bytes())
+                .chain_err(|| format!("Invalid certificate in '{}'", ca_file))?;
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+// Headers from `Name: value; Other: value`, invalid ones left out
+fn extra_headers(headers: &str) -> HeaderMap {
+    let mut map = HeaderMap::new();
+
+    for header in headers.split(';').map(str::trim).filter(|header| !header.is_empty()) {
+        let mut parts = header.splitn(2, ':');
+        let (name, value) = (parts.next().unwrap_or_default().trim(), parts.next().unwrap_or_default().trim());
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                map.insert(name, value);
+            },
+            _ => warn!("Ignoring invalid header '{}'", header),
+        }
+    }
+
+    map
+}
+
+// Loopback, private, link-local and other addresses not on the internet, including
+// IPv6 ones that lead to such an IPv4 address
+fn is_private(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let octets = address.octets();
+            address.is_private() || address.is_loopback() || address.is_link_local() || address.is_multicast()
+                || address.is_documentation()
+                || octets[0] == 0  // This network
+                || octets[0] >= 240  // Reserved, and broadcast
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)  // Carrier-grade NAT
+                || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0)  // Protocol assignments
+                || (octets[0] == 198 && octets[1] & 0xfe == 18)  // Benchmarking
+        },
+        IpAddr::V6(address) => {
+            let segments = address.segments();
+            let embedded = |high: u16, low: u16| is_private(&IpAddr::V4(Ipv4Addr::from((high as u32) << 16 | low as u32)));
+            if let Some(address) = address.to_ipv4_mapped() {
+                return is_private(&IpAddr::V4(address))
+            }
+
+            address.is_loopback() || address.is_unspecified() || address.is_multicast()
+                || segments[0] & 0xfe00 == 0xfc00  // Unique local
+                || segments[0] & 0xffc0 == 0xfe80  // Link-local
+                || segments[0] & 0xffc0 == 0xfec0  // Site-local
+                || (segments[0] == 0x64 && segments[1] == 0xff9b && segments[2] == 1)  // Local NAT64
+                || (segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] && embedded(segments[6], segments[7]))  // NAT64
+                || (segments[0] == 0x2002 && embedded(segments[1], segments[2]))  // 6to4
+        },
+    }
+}
+
+// Type and subtype of a `Content-Type` value, lowercase
+fn mime_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or_default().trim().to_lowercase()
+}
+
+// Extensions of the files of each type, the usual one first
+const EXTENSIONS: &[(&str, &[&str])] = &[
+    ("text/html", &["html", "htm", "shtml", "php", "asp", "aspx", "jsp"]),
+    ("application/xhtml+xml", &["xhtml", "html", "htm"]),
+    ("text/plain", &["txt"]),
+    ("text/xml", &["xml"]),
+    ("application/xml", &["xml"]),
+    ("image/jpeg", &["jpg", "jpeg", "jpe"]),
+    ("image/png", &["png"]),
+    ("image/gif", &["gif"]),
+    ("image/webp", &["webp"]),
+    ("image/svg+xml", &["svg"]),
+    ("image/avif", &["avif"]),
+    ("image/bmp", &["bmp"]),
+    ("image/tiff", &["tif", "tiff"]),
+];
+
+fn extensions(mime_type: &str) -> &'static [&'static str] {
+    EXTENSIONS.iter().find(|(known, _)| *known == mime_type).map_or(&[], |(_, extensions)| extensions)
+}
+
+// Type of the files with `extension`, if it's one of `EXTENSIONS`
+fn mime_type_of(extension: &str) -> Option<&'static str> {
+    EXTENSIONS.iter().find(|(_, extensions)| extensions.contains(&extension)).map(|(mime_type, _)| *mime_type)
+}
+
+// `name` as a file of the download folder: no folders, no characters some systems
+// forbid, not too long, with an extension of `mime_type` if known. Otherwise its own
+// extension is only kept if it's one of `file_type`, e.g. no `.sh` for an image.
+fn safe_filename(name: &str, mime_type: Option<&str>, file_type: DLFileType) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars()
+        .map(|c| if c.is_control() || "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    // Neither hidden, nor `..`, nor ending in a dot or space, which Windows drops
+    let name = name.trim_matches(|c| c == '.' || c == ' ');
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !extension.is_empty() && extension.len() <= 5
+            && extension.chars().all(|c| c.is_ascii_alphanumeric()) => (stem, Some(extension.to_lowercase())),
+        _ => (name, None),
+    };
+    let mut stem: String = stem.chars().take(MAX_NAME_LEN).collect();
+    if stem.is_empty() {
+        stem = String::from("download");
+    }
+    // Devices on Windows, whatever the extension
+    let device = stem.split('.').next().unwrap_or_default().to_uppercase();
+    if ["CON", "PRN", "AUX", "NUL"].contains(&device.as_str())
+        || (device.len() == 4 && (device.starts_with("COM") || device.starts_with("LPT")) && device.ends_with(|c: char| c.is_ascii_digit())) {
+        stem.insert(0, '_');
+    }
+
+    let expected = mime_type.map(extensions).unwrap_or_default();
+    let extension = match extension {
+        Some(extension) if expected.contains(&extension.as_str()) => Some(extension),
+        Some(extension) if expected.is_empty()
+            && mime_type_of(&extension).is_some_and(|mime_type| file_type.accepts(mime_type)) => Some(extension),
+        _ => expected.first().map(|extension| extension.to_string()),
+    };
+
+    match extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem,
+    }
+}
+
+// Whether trying again later may work: timeouts, connection problems and server overload
+fn is_transient(error: &Error) -> bool {
+    match error.kind() {
+        ErrorKind::Timeout(_) => true,
+        ErrorKind::HttpRequest(e) => e.is_timeout() || e.is_connect() || e.status().is_some_and(|status| {
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PultConf;
+    use tiny_http::{Header, Response, Server};
+
+    fn downloader(block_private: bool, proxy: &str) -> (tempfile::TempDir, Downloader) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = PultConf::defaults();
+        cfg.insert("block_private_addresses".into(), block_private.to_string());
+        cfg.insert("proxy".into(), proxy.into());
+        let downloader = Downloader::new(dir.path().into(), DLFileType::Text, &cfg, Task::default()).unwrap();
+        (dir, downloader)
+    }
+
+    fn is_blocked_download(result: Result<PathBuf>) -> bool {
+        matches!(result.map_err(|e| e.0), Err(ErrorKind::Blocked(_)))
+    }
+
+    #[test]
+    fn tells_private_addresses() {
+        let private = [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "0.0.0.0", "169.254.169.254",
+            "100.64.0.1", "100.127.255.254", "192.0.0.8", "198.18.0.1", "224.0.0.1", "255.255.255.255",
+            "::1", "::", "::ffff:127.0.0.1", "::ffff:10.0.0.1", "fc00::1", "fdff::1", "fe80::1", "febf::1",
+            "fec0::1", "ff02::1", "64:ff9b::a00:1", "64:ff9b:1::1", "2002:7f00:1::",
+        ];
+        let public = [
+            "1.1.1.1", "100.63.255.255", "100.128.0.1", "172.32.0.1", "169.255.0.1",
+            "2606:4700::1111", "::ffff:1.1.1.1", "64:ff9b::101:101", "2002:101:101::",
+        ];
+
+        for address in private.iter() {
+            assert!(is_private(&address.parse().unwrap()), "{}", address);
+        }
+        for address in public.iter() {
+            assert!(!is_private(&address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[test]
+    fn makes_safe_filenames() {
+        let cases = [
+            ("../../etc/passwd", None, DLFileType::Text, "passwd"),
+            ("/etc/cron.d/job", None, DLFileType::Text, "job"),
+            ("C:\\Windows\\run.exe", Some("text/html"), DLFileType::Text, "run.html"),
+            ("..", None, DLFileType::Text, "download"),
+            (".bashrc", None, DLFileType::Text, "bashrc"),
+            ("CON.txt", Some("text/plain"), DLFileType::Text, "_CON.txt"),
+            ("a<b>?.jpeg", Some("image/jpeg"), DLFileType::Image, "a_b__.jpeg"),
+            ("photo.png", Some("image/jpeg"), DLFileType::Image, "photo.jpg"),
+            ("page.html", None, DLFileType::Text, "page.html"),
+            ("photo.jpg", None, DLFileType::Image, "photo.jpg"),
+            ("photo.jpg", Some("application/octet-stream"), DLFileType::Image, "photo.jpg"),
+            // Not images, whatever the address says
+            ("run.sh", None, DLFileType::Image, "run"),
+            ("page.html", None, DLFileType::Image, "page"),
+            ("page.html", Some("application/octet-stream"), DLFileType::Image, "page"),
+            ("photo.jpg", None, DLFileType::Text, "photo"),
+        ];
+
+        for (name, mime_type, file_type, expected) in cases.iter() {
+            assert_eq!(safe_filename(name, *mime_type, *file_type), *expected, "{}", name);
+        }
+        assert_eq!(safe_filename(&"a".repeat(300), Some("image/png"), DLFileType::Image).len(), MAX_NAME_LEN + 4);
+    }
+
+    #[test]
+    fn refuses_private_hosts() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().port();
+        let (_dir, blocking) = downloader(true, "");
+
+        let urls = [
+            format!("http://127.0.0.1:{}/", port),
+            format!("http://[::ffff:127.0.0.1]:{}/", port),
+            format!("http://0.0.0.0:{}/", port),
+            format!("http://localhost:{}/", port),
+        ];
+        for url in urls.iter() {
+            assert!(is_blocked_download(blocking.download_from(url.parse().unwrap())), "{}", url);
+        }
+        assert!(server.try_recv().unwrap().is_none());
+
+        // Names are checked as the client looks them up
+        let mut cfg = PultConf::defaults();
+        cfg.insert("block_private_addresses".into(), "true".into());
+        let client = http_client(&cfg, false).unwrap();
+        let error = blocking.runtime.block_on(client.get(format!("http://localhost:{}/", port)).send()).unwrap_err();
+        assert!(is_blocked(&error), "{}", error);
+        assert!(server.try_recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_redirects_to_private_hosts() {
+        // Through a proxy, whose answers are those of the pages
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let proxy = format!("http://127.0.0.1:{}", server.server_addr().port());
+        let (_dir, blocking) = downloader(true, &proxy);
+
+        let targets = ["http://127.0.0.1:1/secret", "http://[fe80::1]/", "http://localhost/"];
+        let pages = Arc::clone(&server);
+        let answers = std::thread::spawn(move || {
+            for target in targets.iter() {
+                let request = pages.recv().unwrap();
+                assert_eq!(request.url(), "http://public.example/");
+                let location = Header::from_bytes(&b"Location"[..], target.as_bytes()).unwrap();
+                request.respond(Response::empty(302).with_header(location)).unwrap();
+            }
+        });
+
+        for target in targets.iter() {
+            let result = blocking.download_from("http://public.example/".parse().unwrap());
+            assert!(is_blocked_download(result), "{}", target);
+        }
+        answers.join().unwrap();
+        assert!(server.try_recv().unwrap().is_none(), "a redirect was followed");
+    }
+}
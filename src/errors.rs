@@ -0,0 +1,116 @@
+// Crate-wide error hierarchy
+error_chain! {
+    foreign_links {
+        Io(std::io::Error);
+        HttpRequest(reqwest::Error);
+        EpubBuilding(epub_builder::Error);
+        ImageReading(image::ImageError);
+        UrlParsing(url::ParseError);
+        Json(serde_json::Error);
+        ConfigFile(confy::ConfyError);
+        Zip(zip::result::ZipError);
+    }
+
+    errors {
+        Config(action: String) {
+            description("configuration error")
+            display("Couldn't {} the configuration", action)
+        }
+
+        InvalidUrl(url: String) {
+            description("invalid URL")
+            display("'{}' is not a valid URL", url)
+        }
+
+        // External program exiting with failure, with what it printed on stderr
+        Tool(program: String, stderr: String) {
+            description("external tool failed")
+            display("{} exited with an error:\n{}", program, stderr)
+        }
+
+        // Malformed request from another application or a link
+        InvalidRequest(reason: String) {
+            description("invalid request")
+            display("Invalid request, {}", reason)
+        }
+
+        Conversion(file: String) {
+            description("conversion failed")
+            display("Couldn't convert '{}'", file)
+        }
+
+        Sending(file: String) {
+            description("sending failed")
+            display("Couldn't send '{}'", file)
+        }
+
+        Fetching(url: String) {
+            description("download failed")
+            display("Couldn't download '{}'", url)
+        }
+
+        Timeout(url: String) {
+            description("download timed out")
+            display("'{}' took too long to answer", url)
+        }
+
+        Cancelled {
+            description("cancelled")
+            display("Cancelled")
+        }
+
+        TooLarge(url: String, limit: u64) {
+            description("download too large")
+            display("'{}' is larger than {} MiB", url, limit / 1024 / 1024)
+        }
+
+        TooManyRedirects(url: String) {
+            description("too many redirects")
+            display("'{}' redirects too many times", url)
+        }
+
+        UnexpectedContent(url: String, content_type: String) {
+            description("unexpected content type")
+            display("'{}' is {}, not the kind of file expected", url, content_type)
+        }
+
+        // Address on this machine or the local network, with `block_private_addresses`
+        Blocked(url: String) {
+            description("private address blocked")
+            display("'{}' is on a private network, downloads from it are blocked", url)
+        }
+
+        Extraction(url: String) {
+            description("article extraction failed")
+            display("Couldn't extract an article from '{}'", url)
+        }
+
+        Packaging(title: String) {
+            description("EPUB packaging failed")
+            display("Couldn't package '{}' as EPUB", title)
+        }
+    }
+}
+
+/// Error flattened for display. Unlike `Error` it is `Clone`,
+/// so it can travel with job events to the GUI.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub summary: String,  // Human readable, outermost error
+    pub details: String,  // Whole cause chain, including captured tool output
+}
+
+impl From<&Error> for Report {
+    fn from(e: &Error) -> Self {
+        let details = e
+            .iter()
+            .map(|cause| cause.to_string())
+            .collect::<Vec<_>>()
+            .join("\ncaused by: ");
+
+        Self {
+            summary: e.to_string(),
+            details,
+        }
+    }
+}
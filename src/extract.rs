@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::Path;
+use std::collections::HashMap;
+
+use soup::prelude::*;
+use url::Url;
+
+use crate::cmd::{ReadabiliPyCmd, ReadabiliPyParser};
+use crate::errors::*;
+use crate::readability;
+use crate::rules::{self, SiteRule};
+use crate::web::Article;
+
+// An article with at least this much text, mostly outside links, is taken as is
+const GOOD_TEXT_LEN: usize = 500;
+const GOOD_LINK_DENSITY: f64 = 0.3;
+
+/// Downloaded page, as given to extractors.
+pub struct Page<'a> {
+    pub url: &'a Url,
+    pub html: &'a str,
+    pub file: &'a Path,  // Same HTML, for external programs
+    pub tmp_dir: &'a Path,  // Scratch space, removed with the page
+}
+
+/// Way of finding the article in a page.
+pub trait ContentExtractor {
+    /// Name in the `extractors` and `site_extractors` settings.
+    fn name(&self) -> &'static str;
+
+    fn extract(&self, page: &Page) -> Result<Article>;
+}
+
+/// Our own Readability implementation.
+pub struct Native;
+
+impl ContentExtractor for Native {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn extract(&self, page: &Page) -> Result<Article> {
+        readability::extract(page.html, page.url).ok_or_else(|| "no article found".into())
+    }
+}
+
+/// The `readabilipy` command, with Mozilla's Readability (through Node.js) or its Python port.
+pub struct ReadabiliPy(pub ReadabiliPyParser);
+
+impl ContentExtractor for ReadabiliPy {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            ReadabiliPyParser::Mozilla => "readabilipy",
+            ReadabiliPyParser::Python => "readabilipy-python",
+        }
+    }
+
+    fn extract(&self, page: &Page) -> Result<Article> {
+        let json_path = page.tmp_dir.join(format!("{}.json", self.name()));
+        ReadabiliPyCmd::new(self.0).json_from_file(page.file, &json_path)?;
+
+        let json_file = fs::File::open(json_path)?;
+        Ok(serde_json::from_reader(json_file)?)
+    }
+}
+
+/// Whole page body, for when nothing stands out as the article.
+pub struct RawBody;
+
+impl ContentExtractor for RawBody {
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+
+    fn extract(&self, page: &Page) -> Result<Article> {
+        Ok(readability::whole_page(page.html, page.url))
+    }
+}
+
+/// Content picked by the `content` selector of a site rule.
+pub struct SiteContent(pub String);
+
+impl ContentExtractor for SiteContent {
+    fn name(&self) -> &'static str {
+        "site"
+    }
+
+    fn extract(&self, page: &Page) -> Result<Article> {
+        let soup = Soup::new(page.html);
+        let nodes = rules::select(&soup.get_handle(), &self.0);
+        if nodes.is_empty() {
+            bail!("nothing matches '{}'", self.0)
+        }
+
+        Ok(readability::from_nodes(&soup, &nodes, page.url))
+    }
+}
+
+fn by_name(name: &str) -> Option<Box<dyn ContentExtractor>> {
+    match name {
+        "native" => Some(Box::new(Native)),
+        "readabilipy" => Some(Box::new(ReadabiliPy(ReadabiliPyParser::Mozilla))),
+        "readabilipy-python" => Some(Box::new(ReadabiliPy(ReadabiliPyParser::Python))),
+        "raw" => Some(Box::new(RawBody)),
+        _ => None,
+    }
+}
+
+/// Engines to try on `url`, in order: the `site_extractors` entry for its domain
+/// (e.g. `example.com=raw; news.org=readabilipy,native`) or else `extractors`.
+fn engines_for(url: &Url, cfg: &HashMap<String, String>) -> Vec<Box<dyn ContentExtractor>> {
+    let host = url.host_str().unwrap_or_default();
+
+    let site_engines = cfg.get("site_extractors").unwrap()
+        .split(';')
+        .filter_map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            Some((parts.next()?.trim(), parts.next()?.trim()))
+        })
+        .find(|(domain, _)| !domain.is_empty() && (host == *domain || host.ends_with(&format!(".{}", domain))))
+        .map(|(_, engines)| engines);
+    let names = site_engines.unwrap_or_else(|| cfg.get("extractors").unwrap());
+
+    names.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let engine = by_name(name);
+            if engine.is_none() {
+                warn!("Unknown extractor '{}'", name);
+            }
+            engine
+        })
+        .collect()
+}
+
+/// The content selected by the site rule, if any matches. Otherwise run the configured
+/// engines on `page` until one gives a good article, or return the best of what they found.
+pub fn best_article(page: &Page, rule: &SiteRule, cfg: &HashMap<String, String>) -> Result<Article> {
+    // Written for the site, so taken whatever its length or links
+    if let Some(selector) = &rule.content {
+        match SiteContent(selector.clone()).extract(page) {
+            Ok(article) => {
+                info!("Article extracted with the site rule");
+                return Ok(article)
+            },
+            Err(e) => info!("Site rule failed on {}: {}", page.url, e),
+        }
+    }
+
+    let mut best: Option<(f64, Article)> = None;
+    let mut last_error = None;
+
+    for engine in engines_for(page.url, cfg) {
+        let article = match engine.extract(page) {
+            Ok(article) => article,
+            Err(e) => {
+                info!("{} extractor failed on {}: {}", engine.name(), page.url, e);
+                last_error = Some(e);
+                continue
+            },
+        };
+
+        let (text_len, link_density) = quality(&article);
+        debug!("{} extractor: {} characters, link density {:.2}", engine.name(), text_len, link_density);
+        if text_len >= GOOD_TEXT_LEN && link_density <= GOOD_LINK_DENSITY {
+            info!("Article extracted with {}", engine.name());
+            return Ok(article)
+        }
+
+        let score = text_len as f64 * (1.0 - link_density);
+        if score > 0.0 && best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, article));
+        }
+    }
+
+    match (best, last_error) {
+        (Some((_, article)), _) => Ok(article),
+        (None, Some(e)) => Err(e),
+        (None, None) => bail!("no article found"),
+    }
+}
+
+// Length of the visible text and share of it inside links
+fn quality(article: &Article) -> (usize, f64) {
+    let soup = match &article.content {
+        Some(content) => Soup::new(content),
+        None => return (0, 0.0),
+    };
+
+    let text_len = soup.text().split_whitespace().map(|word| word.chars().count() + 1).sum::<usize>();
+    if text_len == 0 {
+        return (0, 0.0)
+    }
+
+    let link_len: usize = soup.tag("a").find_all()
+        .map(|a| a.text().split_whitespace().map(|word| word.chars().count() + 1).sum::<usize>())
+        .sum();
+    (text_len, link_len as f64 / text_len as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PultConf;
+
+    #[test]
+    fn keeps_short_articles() {
+        let html = "<html><head><title>Quick note</title></head><body>\
+            <nav><a href=\"/\">Home</a> <a href=\"/about\">About</a></nav>\
+            <article><p>Just a short post, to say that the new release is out, and that it fixes the sync bug.</p></article>\
+            </body></html>";
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("page.html");
+        fs::write(&file, html).unwrap();
+        let url = Url::parse("https://example.com/note").unwrap();
+        let page = Page { url: &url, html, file: &file, tmp_dir: dir.path() };
+
+        // Not long enough to stop at the first engine, still the best there is
+        let article = best_article(&page, &SiteRule::default(), &PultConf::defaults()).unwrap();
+        assert!(article.content.unwrap().contains("fixes the sync bug"));
+    }
+
+    #[test]
+    fn trusts_site_rules() {
+        let html = "<html><body><div class=\"quote\"><a href=\"/q/1\">Short and linked</a></div>\
+            <div class=\"main\"><p>A much longer text, that generic extraction would rather pick as the article.</p></div>\
+            </body></html>";
+        let dir = tempfile::tempdir().unwrap();
+        let url = Url::parse("https://example.com/q/1").unwrap();
+        let page = Page { url: &url, html, file: &dir.path().join("page.html"), tmp_dir: dir.path() };
+        let mut cfg = PultConf::defaults();
+        cfg.insert("extractors".into(), "native".into());
+
+        let rule = SiteRule { content: Some(".quote".into()), ..SiteRule::default() };
+        let content = best_article(&page, &rule, &cfg).unwrap().content.unwrap();
+        assert!(content.contains("Short and linked") && !content.contains("much longer"), "{}", content);
+
+        // Extractors take over when the page changed
+        let rule = SiteRule { content: Some(".gone".into()), ..SiteRule::default() };
+        let content = best_article(&page, &rule, &cfg).unwrap().content.unwrap();
+        assert!(content.contains("much longer"), "{}", content);
+    }
+}
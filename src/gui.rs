@@ -2,16 +2,25 @@ extern crate glib;
 extern crate gio;
 extern crate gtk;
 use glib::clone;
+use gio::prelude::*;
 use gtk::prelude::*;
 
 use std::sync::{Arc, Mutex};
-use std::env;
-use std::fs;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::cmd::CalibreCmd;
-use crate::web::Article;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::jobs::{JobEvent, JobId, JobPool, JobSource, JobState};
+use crate::notify::{self, Notifier};
 use crate::config::PultConf;
+#[cfg(unix)]
+use crate::dbus;
+use crate::images;
+use crate::server;
+use crate::remote::{Invocation, Request};
+use crate::errors::*;
+use crate::logging::{self, LogEntry};
 
 struct CfgField {
     label: gtk::Label,
@@ -44,15 +53,43 @@ struct CfgFields {
     user: CfgField,
     password: CfgField,
     to_ext: CfgField,
+    max_jobs: CfgField,
+    http_address: CfgField,
+    http_token: CfgField,
+    output_dir: CfgField,
+    name_template: CfgField,
+    user_agent: CfgField,
+    proxy: CfgField,
+}
+
+struct JobRow {
+    source: JobSource,
+    cfg: HashMap<String, String>,  // Config it ran with, for retries
+    state_lbl: gtk::Label,
+    cancel_btn: gtk::Button,
+    details_btn: gtk::Button,
+    open_btn: gtk::Button,
+    output: Option<std::path::PathBuf>,  // Saved article, once there is one
+    report: Option<Report>,  // Why the job failed, if it did
 }
 
+type JobRows = Rc<RefCell<HashMap<JobId, JobRow>>>;
+
+// Files waiting for the Send button, each with the config of the profile it was queued with
+type Selection = Vec<(std::path::PathBuf, HashMap<String, String>)>;
+
 pub struct Gui {
+    app: gtk::Application,
     win: gtk::ApplicationWindow,
+    headerbar: gtk::HeaderBar,
     vbox: gtk::Box,
     file_img: gtk::Image,
-    source_files: Arc<Mutex<Vec<std::path::PathBuf>>>,  // TODO: use RefCell
-    open_sender: glib::Sender<Vec<std::path::PathBuf>>,
+    source_files: Arc<Mutex<Selection>>,  // TODO: use RefCell
+    open_sender: glib::Sender<Selection>,
     cfg: HashMap<String, String>,
+    jobs: JobPool,
+    job_list: gtk::ListBox,
+    job_rows: JobRows,
 }
 
 impl Gui {
@@ -68,39 +105,190 @@ impl Gui {
         vbox.set_margin_bottom(10 as i32);
         win.add(&vbox);
 
+        let headerbar = gtk::HeaderBar::new();
+
         // Images
         let file_img = gtk::Image::from_icon_name(Some("document-open"), gtk::IconSize::Button);
 
         // Shared Ref
         let (open_sender, open_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
-        let source_files = Arc::new(Mutex::new(Vec::new())); // Store String paths
+        let source_files = Arc::new(Mutex::new(Vec::new())); // Store paths with their config
 
         // Receiver from dialog sender
         let source_files_clone = Arc::clone(&source_files);
-        open_receiver.attach(None, move |files: Vec<std::path::PathBuf>| {
-            for file in files {
-                // Push to source_files vector
-                let mut m = source_files_clone.lock().unwrap();
-                m.push(file);
+        let headerbar_clone = headerbar.clone();
+        open_receiver.attach(None, move |files: Selection| {
+            let mut m = source_files_clone.lock().unwrap();
+            for (file, cfg) in files {
+                // Push to source_files vector, once, with the latest profile
+                match m.iter_mut().find(|(queued, _)| *queued == file) {
+                    Some(queued) => queued.1 = cfg,
+                    None => m.push((file, cfg)),
+                }
             }
+            headerbar_clone.set_subtitle(queued_subtitle(m.len()).as_deref());
             glib::Continue(true)
         });
 
         // Reload Conf
-        let cfg = PultConf::reload();
+        let cfg = match PultConf::reload() {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                show_error(&win, &Report::from(&e));
+                PultConf::defaults()
+            },
+        };
+
+        // Worker pool, reporting job state changes to the main loop
+        let parallelism = cfg.get("max_jobs").unwrap().parse().unwrap_or(1);
+        let jobs = JobPool::new(parallelism);
+        let (job_sender, job_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        jobs.connect_event(move |event: &JobEvent| {
+            let _ = job_sender.send(event.clone());
+        });
+
+        // Let other applications and devices use the same queue
+        #[cfg(unix)]
+        dbus::spawn(jobs.clone(), cfg.clone());
+        server::spawn(jobs.clone(), cfg.clone());
+
+        // Job list, one row per queued file
+        let job_list = gtk::ListBox::new();
+        job_list.set_selection_mode(gtk::SelectionMode::None);
+
+        let job_rows: JobRows = Rc::new(RefCell::new(HashMap::new()));
+        // Every job ends up here, whoever submitted it
+        let mut notifier = Notifier::new(application.clone());
+
+        let job_list_clone = job_list.clone();
+        let job_rows_clone = Rc::clone(&job_rows);
+        let jobs_clone = jobs.clone();
+        let win_clone = win.clone();
+        job_receiver.attach(None, move |event: JobEvent| {
+            let mut job_rows = job_rows_clone.borrow_mut();
+            let job_row = job_rows.entry(event.id).or_insert_with(|| {
+                let row = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+                let name_lbl = gtk::Label::new(Some(&event.source.name()));
+                name_lbl.set_hexpand(true);
+                name_lbl.set_halign(gtk::Align::Start);
+                let state_lbl = gtk::Label::new(None);
+                let cancel_btn = gtk::Button::from_icon_name(Some("process-stop"), gtk::IconSize::Button);
+                cancel_btn.set_tooltip_text(Some("Cancel"));
+
+                let jobs_clone = jobs_clone.clone();
+                let id = event.id;
+                cancel_btn.connect_clicked(move |_| {
+                    jobs_clone.cancel(id);
+                });
+
+                // Only shown once the job failed
+                let details_btn = gtk::Button::from_icon_name(Some("dialog-information"), gtk::IconSize::Button);
+                details_btn.set_tooltip_text(Some("Show details"));
+                details_btn.set_no_show_all(true);
+
+                let job_rows = Rc::clone(&job_rows_clone);
+                let win = win_clone.clone();
+                details_btn.connect_clicked(move |_| show_job_errors(&win, &job_rows, &[id]));
+
+                // Only shown once an article is saved
+                let open_btn = gtk::Button::from_icon_name(Some("folder-open"), gtk::IconSize::Button);
+                open_btn.set_tooltip_text(Some("Open folder"));
+                open_btn.set_no_show_all(true);
+
+                let job_rows = Rc::clone(&job_rows_clone);
+                open_btn.connect_clicked(move |_| {
+                    let output = job_rows.borrow().get(&id).and_then(|row| row.output.clone());
+                    if let Some(folder) = output.as_ref().and_then(|file| file.parent()) {
+                        show_folder(folder);
+                    }
+                });
+
+                row.add(&name_lbl);
+                row.add(&state_lbl);
+                row.add(&open_btn);
+                row.add(&details_btn);
+                row.add(&cancel_btn);
+                job_list_clone.add(&row);
+                row.show_all();
+
+                JobRow {
+                    source: event.source.clone(),
+                    cfg: event.cfg.clone(),
+                    state_lbl,
+                    cancel_btn,
+                    details_btn,
+                    open_btn,
+                    output: None,
+                    report: None,
+                }
+            });
+
+            job_row.state_lbl.set_text(&event.describe());
+            job_row.cancel_btn.set_sensitive(!event.state.is_finished());
+            if let JobState::Failed(report) = &event.state {
+                job_row.details_btn.show();
+                job_row.report = Some(report.clone());
+            }
+            if event.state == JobState::Saved {
+                job_row.open_btn.show();
+                job_row.output = event.output.clone();
+            }
+
+            notifier.job_changed(&event);
+            glib::Continue(true)
+        });
 
         Self {
+            app: application.clone(),
             win,
+            headerbar,
             vbox,
             file_img,
             source_files,
             open_sender,
             cfg,
+            jobs,
+            job_list,
+            job_rows,
         }
     }
 
-    fn build_headerbar(&self) -> gtk::HeaderBar {
-        let headerbar = gtk::HeaderBar::new();
+    // Targets of the buttons in job notifications
+    fn add_actions(&self) {
+        let open_folder = gio::SimpleAction::new("open-folder", Some(glib::VariantTy::new("s").unwrap()));
+        open_folder.connect_activate(|_, target| {
+            if let Some(folder) = target.and_then(|t| t.get_str()) {
+                show_folder(std::path::Path::new(folder));
+            }
+        });
+
+        let retry = gio::SimpleAction::new("retry", Some(glib::VariantTy::new("s").unwrap()));
+        let jobs = self.jobs.clone();
+        let job_rows = Rc::clone(&self.job_rows);
+        retry.connect_activate(move |_, target| {
+            // Each job again with its own profile, not the current settings
+            let sources: Vec<(JobSource, HashMap<String, String>)> = notify::parse_ids(target).iter()
+                .filter_map(|id| job_rows.borrow().get(id).map(|row| (row.source.clone(), row.cfg.clone())))
+                .collect();
+
+            jobs.submit_batch(sources);
+        });
+
+        let show_details = gio::SimpleAction::new("show-details", Some(glib::VariantTy::new("s").unwrap()));
+        let win = self.win.clone();
+        let job_rows = Rc::clone(&self.job_rows);
+        show_details.connect_activate(move |_, target| {
+            win.present();
+            show_job_errors(&win, &job_rows, &notify::parse_ids(target));
+        });
+
+        self.app.add_action(&open_folder);
+        self.app.add_action(&retry);
+        self.app.add_action(&show_details);
+    }
+
+    fn build_headerbar(&self) {
+        let headerbar = &self.headerbar;
 
         let select_files_btn = gtk::Button::new();
         select_files_btn.add(&self.file_img);
@@ -108,6 +296,7 @@ impl Gui {
         // Dialog for getting source files
         let win = &self.win;
         let sender_clone = self.open_sender.clone();
+        let cfg = self.cfg.clone();
         select_files_btn.connect_clicked(clone!(@weak win => move |_| {
             let dialog = gtk::FileChooserDialog::new(
                 Some("Choose a file"),
@@ -122,11 +311,14 @@ impl Gui {
 
             dialog.set_select_multiple(true);
             let sender_clone = sender_clone.clone();
+            let cfg = cfg.clone();
 
             dialog.connect_response(move |dialog, response| {
                 if response == gtk::ResponseType::Ok {
-                    let files = dialog.get_filenames();
-                    sender_clone.send(files).unwrap();
+                    let files = dialog.get_filenames().into_iter().map(|file| (file, cfg.clone())).collect();
+                    if let Err(e) = sender_clone.send(files) {
+                        error!("Couldn't add the chosen files: {}", e);
+                    }
                 }
                 dialog.close();
             });
@@ -137,9 +329,47 @@ impl Gui {
 
         headerbar.add(&select_files_btn);  // Add select button to headerbar
         headerbar.set_show_close_button(true);  // Show close/extend/minimize in headerbar
-        self.win.set_titlebar(Some(&headerbar));  // Set this headerbar as title bar (the top one)
+        self.win.set_titlebar(Some(headerbar));  // Set this headerbar as title bar (the top one)
+    }
+
+    /// Bring the window to the front.
+    pub fn present(&self) {
+        self.win.present();
+    }
+
+    /// Queue requests coming from the command line or other applications.
+    /// Returns the ids of the jobs started right away.
+    pub fn queue(&self, invocation: Invocation) -> Result<Vec<JobId>> {
+        let cfg = match &invocation.profile {
+            Some(profile) => PultConf::reload_profile(profile)?,
+            None => self.cfg.clone(),
+        };
+
+        let mut selected = Vec::new();
+        let mut sent = Vec::new();
+        let mut articles = Vec::new();
+
+        for request in invocation.requests {
+            match request {
+                Request::Open(file) => selected.push((file, cfg.clone())),
+                Request::Send(file) => sent.push((JobSource::File(file), cfg.clone())),
+                Request::Url(url) => articles.push(JobSource::Url(url)),
+            }
+        }
+
+        if !selected.is_empty() {
+            let _ = self.open_sender.send(selected);
+        }
 
-        headerbar
+        // Files sent together are notified together, articles one by one
+        let ids = self.jobs.submit_batch(sent);
+        let articles = articles.into_iter().map(|url| self.jobs.submit(url, cfg.clone()));
+
+        Ok(ids.into_iter().chain(articles).collect())
+    }
+
+    pub fn show_error(&self, e: &Error) {
+        show_error(&self.win, &Report::from(e));
     }
 
     fn build_url_box(&self) {
@@ -152,14 +382,30 @@ impl Gui {
         let download_btn = gtk::Button::with_label("Download");
         download_btn.set_property_expand(false);
 
+        // Defaults to the profile setting, the article goes to the output folder either way
+        let download_only = gtk::CheckButton::with_label("Download only");
+        download_only.set_active(!self.cfg.get("send_articles").and_then(|value| value.parse().ok()).unwrap_or(false));
+
         let url_buffer_clone = url_field.buffer.clone();
+        let jobs_clone = self.jobs.clone();
+        let cfg_clone = self.cfg.clone();
+        let download_only_clone = download_only.clone();
         download_btn.connect_clicked(move |_| {
-            let _article = Article::epub_from_url(url_buffer_clone.get_text());
-            // println!("{:?}", no.unwrap());
+            let url = url_buffer_clone.get_text();
+            if url.trim().is_empty() {
+                return
+            }
+
+            let mut cfg = cfg_clone.clone();
+            let send = !download_only_clone.get_active();
+            cfg.insert("send_articles".into(), send.to_string());
+
+            jobs_clone.submit(JobSource::Url(url.trim().into()), cfg);
         });  // Connect clicked button
 
         url_box.add(&url_field.label);
         url_box.add(&url_field.entry);
+        url_box.add(&download_only);
         url_box.add(&download_btn);
         url_box.set_margin_bottom(20);
 
@@ -175,6 +421,13 @@ impl Gui {
             user: CfgField::new("User:", self.cfg.get("username").unwrap()),
             password: CfgField::new("Password:", self.cfg.get("password").unwrap()),
             to_ext: CfgField::new("Extension:", self.cfg.get("to_ext").unwrap()),
+            max_jobs: CfgField::new("Parallel jobs:", self.cfg.get("max_jobs").unwrap()),
+            http_address: CfgField::new("Web address:", self.cfg.get("http_address").unwrap()),
+            http_token: CfgField::new("Web token:", self.cfg.get("http_token").unwrap()),
+            output_dir: CfgField::new("Output folder:", self.cfg.get("output_dir").unwrap()),
+            name_template: CfgField::new("File names:", self.cfg.get("name_template").unwrap()),
+            user_agent: CfgField::new("User agent:", self.cfg.get("user_agent").unwrap()),
+            proxy: CfgField::new("Proxy:", self.cfg.get("proxy").unwrap()),
         }
     }
 
@@ -215,12 +468,68 @@ impl Gui {
         let del_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
         let del_sent = gtk::Switch::new();
         let del_sent_lbl = gtk::Label::new(Some("Delete sents"));
-        del_sent.set_active(self.cfg.get("del_sent").unwrap().parse().unwrap());
+        del_sent.set_active(self.cfg.get("del_sent").and_then(|value| value.parse().ok()).unwrap_or(false));
         del_box.add(&del_sent_lbl);
         del_box.add(&del_sent);
         // grid.attach(&del_sent_lbl, 2, 3, 1, 1);
         grid.attach(&del_box, 2, 3, 1, 1);
 
+        // Row 4
+        grid.attach(&flds.max_jobs.label, 0, 4, 1, 1);
+        grid.attach(&flds.max_jobs.entry, 1, 4, 1, 1);
+
+        let http_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        let http_enabled = gtk::Switch::new();
+        let http_enabled_lbl = gtk::Label::new(Some("Web access"));
+        http_enabled.set_active(self.cfg.get("http_enabled").and_then(|value| value.parse().ok()).unwrap_or(false));
+        http_box.add(&http_enabled_lbl);
+        http_box.add(&http_enabled);
+        grid.attach(&http_box, 2, 4, 1, 1);
+
+        // Row 5
+        grid.attach(&flds.http_address.label, 0, 5, 1, 1);
+        grid.attach(&flds.http_address.entry, 1, 5, 1, 1);
+        grid.attach(&flds.http_token.label, 2, 5, 1, 1);
+        grid.attach(&flds.http_token.entry, 3, 5, 1, 1);
+
+        // Row 6
+        grid.attach(&flds.output_dir.label, 0, 6, 1, 1);
+        grid.attach(&flds.output_dir.entry, 1, 6, 1, 1);
+        flds.output_dir.entry.set_placeholder_text(Some("Downloads"));
+
+        let send_articles_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        let send_articles = gtk::Switch::new();
+        let send_articles_lbl = gtk::Label::new(Some("Send articles"));
+        send_articles.set_active(self.cfg.get("send_articles").and_then(|value| value.parse().ok()).unwrap_or(false));
+        send_articles_box.add(&send_articles_lbl);
+        send_articles_box.add(&send_articles);
+        grid.attach(&send_articles_box, 2, 6, 1, 1);
+
+        // Row 7
+        grid.attach(&flds.name_template.label, 0, 7, 1, 1);
+        grid.attach(&flds.name_template.entry, 1, 7, 1, 1);
+        flds.name_template.entry.set_tooltip_text(Some("{title}, {author}, {site} and {date}"));
+
+        let device_lbl = gtk::Label::new(Some("Device:"));
+        let device = gtk::ComboBoxText::new();
+        device.append(Some(""), "Any (images as they are)");
+        for name in images::device_names() {
+            device.append(Some(name), name);
+        }
+        device.set_active_id(Some(self.cfg.get("device").unwrap().as_str()));
+        device.set_tooltip_text(Some("Article images are shrunk to its screen, and made grey for e-ink ones"));
+        grid.attach(&device_lbl, 2, 7, 1, 1);
+        grid.attach(&device, 3, 7, 1, 1);
+
+        // Row 8
+        grid.attach(&flds.user_agent.label, 0, 8, 1, 1);
+        grid.attach(&flds.user_agent.entry, 1, 8, 1, 1);
+        flds.user_agent.entry.set_placeholder_text(Some("Kindle-pult"));
+        grid.attach(&flds.proxy.label, 2, 8, 1, 1);
+        grid.attach(&flds.proxy.entry, 3, 8, 1, 1);
+        flds.proxy.entry.set_placeholder_text(Some("None"));
+        flds.proxy.entry.set_tooltip_text(Some("http://host:port, or socks5h://127.0.0.1:9050 for Tor"));
+
         self.vbox.add(&grid);
 
         // Cfg Button Box
@@ -232,28 +541,120 @@ impl Gui {
         let save_button = gtk::Button::with_label("Save settings");
         save_button.set_property_expand(false);
 
+        let win = self.win.clone();
         save_button.connect_clicked(move |_| {
-            let new_conf = PultConf {
-                del_sent: {if del_sent.get_state() { "true".into() } else { "false".into() }},
-                to_ext: flds.to_ext.buffer.get_text(),
-                smtp: flds.smtp.buffer.get_text(),
-                port: flds.port.buffer.get_text(),
-                username: flds.user.buffer.get_text(),
-                password: flds.password.buffer.get_text(),
-                from_mail: flds.from_mail.buffer.get_text(),
-                to_mail: flds.to_mail.buffer.get_text(),
-            };
+            // Start from the stored conf, so settings without a field here are kept
+            let mut new_conf: PultConf = confy::load("kindle-pult").unwrap_or_else(|e| {
+                warn!("Couldn't load config, saving these settings over the defaults: {}", e);
+                PultConf::default()
+            });
+            new_conf.del_sent = if del_sent.get_state() { "true".into() } else { "false".into() };
+            new_conf.to_ext = flds.to_ext.buffer.get_text();
+            new_conf.smtp = flds.smtp.buffer.get_text();
+            new_conf.port = flds.port.buffer.get_text();
+            new_conf.username = flds.user.buffer.get_text();
+            new_conf.password = flds.password.buffer.get_text();
+            new_conf.from_mail = flds.from_mail.buffer.get_text();
+            new_conf.to_mail = flds.to_mail.buffer.get_text();
+            new_conf.max_jobs = flds.max_jobs.buffer.get_text();
+            new_conf.http_enabled = if http_enabled.get_state() { "true".into() } else { "false".into() };
+            new_conf.http_address = flds.http_address.buffer.get_text();
+            new_conf.http_token = flds.http_token.buffer.get_text();
+            new_conf.send_articles = if send_articles.get_state() { "true".into() } else { "false".into() };
+            new_conf.output_dir = flds.output_dir.buffer.get_text();
+            new_conf.name_template = flds.name_template.buffer.get_text();
+            new_conf.device = device.get_active_id().map(|id| id.to_string()).unwrap_or_default();
+            new_conf.user_agent = flds.user_agent.buffer.get_text();
+            new_conf.proxy = flds.proxy.buffer.get_text();
+
+            // Web access needs a token, make one up
+            if http_enabled.get_state() && new_conf.http_token.len() < 16 {
+                new_conf.http_token = server::new_token();
+                flds.http_token.buffer.set_text(&new_conf.http_token);
+            }
 
-            let _ = confy::store("kindle-pult", new_conf);
-            let _ = PultConf::reload();
+            if let Err(e) = new_conf.store() {
+                show_error(&win, &Report::from(&e));
+            }
         });  // Connect clicked button
 
         // btn_box.add(&save_button);
-        grid.attach(&save_button, 3, 3, 1, 1);
+        grid.attach(&save_button, 3, 9, 1, 1);
         // self.vbox.add(&btn_box);
     }  // build_cfg_ui
 
+    fn build_log_viewer(&self) {
+        let expander = gtk::Expander::new(Some("Log"));
+        let log_box = gtk::Box::new(gtk::Orientation::Vertical, 5);
+
+        // Filter by job
+        let filter = gtk::ComboBoxText::new();
+        filter.append(Some("all"), "All jobs");
+        filter.set_active_id(Some("all"));
+
+        let view = gtk::TextView::new();
+        view.set_editable(false);
+        view.set_wrap_mode(gtk::WrapMode::WordChar);
+        let buffer = view.get_buffer().unwrap();
+
+        let scrolled = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+        scrolled.set_size_request(-1, 160);
+        scrolled.add(&view);
+
+        // Records come from any thread, forward them to the main loop
+        let (log_sender, log_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        logging::connect_entry(move |entry: &LogEntry| {
+            let _ = log_sender.send(entry.clone());
+        });
+
+        let matches_filter = |entry: &LogEntry, filter: &gtk::ComboBoxText| {
+            match filter.get_active_id() {
+                Some(id) if id != "all" => entry.job.map(|job| job.to_string()) == Some(id.to_string()),
+                _ => true,
+            }
+        };
+
+        // Refill the view when the filter changes
+        let buffer_clone = buffer.clone();
+        filter.connect_changed(move |filter| {
+            buffer_clone.set_text("");
+            for entry in logging::entries().iter().filter(|e| matches_filter(e, filter)) {
+                buffer_clone.insert(&mut buffer_clone.get_end_iter(), &format!("{}\n", entry.format()));
+            }
+        });
+        let buffer_clone = buffer.clone();
+
+        // Append new records, offering new jobs in the filter
+        let mut known_jobs = HashSet::new();
+        let filter_clone = filter.clone();
+        log_receiver.attach(None, move |entry: LogEntry| {
+            if let Some(job) = entry.job {
+                if known_jobs.insert(job) {
+                    filter_clone.append(Some(&job.to_string()), &format!("Job {}", job));
+                }
+            }
+
+            if matches_filter(&entry, &filter_clone) {
+                buffer.insert(&mut buffer.get_end_iter(), &format!("{}\n", entry.format()));
+            }
+            glib::Continue(true)
+        });
+
+        // Records logged before the window existed
+        for entry in logging::entries() {
+            buffer_clone.insert(&mut buffer_clone.get_end_iter(), &format!("{}\n", entry.format()));
+        }
+
+        log_box.add(&filter);
+        log_box.add(&scrolled);
+        expander.add(&log_box);
+        self.vbox.add(&expander);
+    }
+
     pub fn build(&self) {
+        // Notification actions
+        self.add_actions();
+
         // HeaderBar
         self.build_headerbar();
 
@@ -264,46 +665,47 @@ impl Gui {
         let cfg_fields = self.make_cfg_fields();
         self.build_cfg_ui(cfg_fields);
 
+        // Job list
+        let scrolled = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+        scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        scrolled.set_size_request(-1, 120);
+        scrolled.set_vexpand(true);
+        scrolled.add(&self.job_list);
+        self.vbox.add(&scrolled);
+
+        let btn_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+
         // Send button
         let send_button = gtk::Button::with_label("Send");
 
         let source_files_clone = Arc::clone(&self.source_files);
-        let cfg_clone = self.cfg.clone();
+        let jobs_clone = self.jobs.clone();
+        let headerbar_clone = self.headerbar.clone();
         send_button.connect_clicked(move |_| {  // On clicked send button...
-            let files = source_files_clone.lock().unwrap();
-
-            for file in &*files {
-                // Check file and its extension
-                if file.exists() {
-                    // CD in file directory
-                    let file_dir = file.parent().unwrap();
-                    let _cd_success = env::set_current_dir(&file_dir);
-                    let from_ext = file.extension().unwrap().to_str().unwrap();
-                    let to_ext = cfg_clone.get("to_ext").unwrap();
-
-                    if from_ext == to_ext {
-                        println!("Conversion unnecessary");
-                    } else {
-                        let _conv_output = CalibreCmd::convert(file.to_str().unwrap(), &to_ext);
-                    }
-                } else {
-                    println!("File not found.");
-                    return
-                }
-
-                let filename = file.file_stem().unwrap();
-                let _send_output = CalibreCmd::send(filename.to_str().unwrap(), cfg_clone.clone());
-
-                // Delete sent file?
-                if cfg_clone.get("del_sent").unwrap().parse().unwrap() {
-                    let _del_result = fs::remove_file(file);
-                }
-            }
+            let mut files = source_files_clone.lock().unwrap();
+
+            // Hand queued files over to the worker pool, as one batch
+            let files = files.drain(..)
+                .map(|(file, cfg)| (JobSource::File(file), cfg))
+                .collect();
+            jobs_clone.submit_batch(files);
+            headerbar_clone.set_subtitle(None);
         });
 
-        send_button.set_property_expand(false);
+        send_button.set_hexpand(true);
         send_button.set_widget_name("suggested-action");  // Mark as primary
-        self.vbox.add(&send_button);
+
+        // Cancel all button
+        let cancel_all_button = gtk::Button::with_label("Cancel all");
+        let jobs_clone = self.jobs.clone();
+        cancel_all_button.connect_clicked(move |_| jobs_clone.cancel_all());
+
+        btn_box.add(&send_button);
+        btn_box.add(&cancel_all_button);
+        self.vbox.add(&btn_box);
+
+        // Log Area
+        self.build_log_viewer();
 
         // Win final settings
         self.win.set_title("Kindle-pult");
@@ -311,3 +713,73 @@ impl Gui {
         self.win.show_all();
     }
 }
+
+fn queued_subtitle(files: usize) -> Option<String> {
+    match files {
+        0 => None,
+        1 => Some("1 file ready to send".into()),
+        n => Some(format!("{} files ready to send", n)),
+    }
+}
+
+/// Modal error dialog: the summary on top, technical details folded in an expander.
+fn show_error<W: IsA<gtk::Window>>(parent: &W, report: &Report) {
+    let dialog = gtk::MessageDialog::new(
+        Some(parent),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Error,
+        gtk::ButtonsType::Close,
+        &report.summary,
+    );
+
+    let details = gtk::TextView::new();
+    details.set_editable(false);
+    details.set_wrap_mode(gtk::WrapMode::WordChar);
+    if let Some(buffer) = details.get_buffer() {
+        buffer.set_text(&report.details);
+    }
+
+    let scrolled = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    scrolled.set_size_request(480, 160);
+    scrolled.add(&details);
+
+    let expander = gtk::Expander::new(Some("Details"));
+    expander.add(&scrolled);
+
+    if let Some(area) = dialog.get_message_area().and_then(|w| w.downcast::<gtk::Box>().ok()) {
+        area.add(&expander);
+    }
+
+    dialog.connect_response(|dialog, _| dialog.close());
+    dialog.show_all();
+}
+
+/// Error dialog gathering the failures of several jobs.
+fn show_job_errors<W: IsA<gtk::Window>>(parent: &W, job_rows: &JobRows, ids: &[JobId]) {
+    let job_rows = job_rows.borrow();
+    let reports: Vec<&Report> = ids.iter()
+        .filter_map(|id| job_rows.get(id).and_then(|row| row.report.as_ref()))
+        .collect();
+
+    match reports.as_slice() {
+        [] => (),
+        [report] => show_error(parent, report),
+        reports => {
+            let report = Report {
+                summary: format!("{} jobs failed", reports.len()),
+                details: reports.iter()
+                    .map(|r| format!("{}\n{}", r.summary, r.details))
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            };
+            show_error(parent, &report);
+        },
+    }
+}
+
+fn show_folder(folder: &std::path::Path) {
+    let uri = gio::File::new_for_path(folder).get_uri();
+    if let Err(e) = gio::AppInfo::launch_default_for_uri(&uri, None::<&gio::AppLaunchContext>) {
+        warn!("Couldn't open {}: {}", folder.display(), e);
+    }
+}
@@ -0,0 +1,461 @@
+use std::fs;
+use std::io::Write;
+use std::rc::Rc;
+use std::thread;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use html5ever::{Attribute, LocalName, Namespace, QualName};
+use html5ever::rcdom::{Handle, NodeData};
+use image::{DynamicImage, GenericImageView, GrayImage, ImageFormat, ImageOutputFormat, Luma};
+use image::imageops::FilterType;
+use soup::prelude::*;
+use url::Url;
+
+use crate::cmd::{self, ImageMagickCmd};
+use crate::errors::*;
+use crate::rules;
+
+// Quality of the JPEGs we encode, losses don't show on e-readers
+const JPEG_QUALITY: u8 = 85;
+
+// E-ink makes mid tones look washed out, darken them
+const E_INK_GAMMA: f32 = 1.8;
+// The only ImageMagick coders we run on downloaded images
+const MAGICK_FORMATS: &[&str] = &["webp", "avif", "heic", "tiff", "bmp", "ico"];
+
+// Grey levels shown by e-ink screens
+const GREY_LEVELS: u8 = 16;
+// Share of the pixels in as many tones as there are grey levels, from which an image is a drawing
+const DRAWING_SHARE: f64 = 0.8;
+
+// Screen width images are chosen for without a device
+const DEFAULT_WIDTH: u32 = 1072;
+// Width in CSS pixels `1x` image candidates are taken to have, without a `width` attribute
+const CSS_WIDTH: u32 = 600;
+
+// Attributes where lazy loading scripts find the real image, best first
+const LAZY_SRCSETS: &[&str] = &["data-srcset", "data-lazy-srcset", "srcset"];
+const LAZY_SRCS: &[&str] = &[
+    "data-src", "data-lazy-src", "data-original", "data-hi-res-src", "data-full-src", "data-lazy", "data-url", "src",
+];
+
+/// Screen of a reading device, images are fitted to it.
+pub struct Device {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub grey: bool,  // E-ink without colours
+}
+
+// Profiles for the `device` setting
+const DEVICES: &[Device] = &[
+    Device { name: "kindle", width: 600, height: 800, grey: true },
+    Device { name: "kindle-paperwhite", width: 1072, height: 1448, grey: true },
+    Device { name: "kindle-oasis", width: 1264, height: 1680, grey: true },
+    Device { name: "kindle-scribe", width: 1860, height: 2480, grey: true },
+    Device { name: "kobo-clara", width: 1072, height: 1448, grey: true },
+    Device { name: "kobo-libra-colour", width: 1264, height: 1680, grey: false },
+    Device { name: "tablet", width: 1600, height: 2560, grey: false },
+];
+
+/// Names of the known devices.
+pub fn device_names() -> impl Iterator<Item = &'static str> {
+    DEVICES.iter().map(|device| device.name)
+}
+
+/// Device set in `cfg`, if images should be optimized for one.
+pub fn device_for(cfg: &HashMap<String, String>) -> Option<&'static Device> {
+    let name = cfg.get("device").map(|name| name.trim()).unwrap_or_default();
+    if name.is_empty() {
+        return None
+    }
+
+    let device = DEVICES.iter().find(|device| device.name == name);
+    if device.is_none() {
+        warn!("Unknown device '{}', images are left as they are", name);
+    }
+    device
+}
+
+/// `html` with the address of each image in its `src`, for images loaded by scripts,
+/// kept in `<noscript>` or offered in several sizes (`srcset`, `<picture>`), choosing
+/// the one fitting a screen `width` pixels wide. Tracking pixels and images with
+/// nothing but inline data go.
+pub fn resolve_sources(html: &str, base_url: &Url, width: Option<u32>) -> String {
+    let soup = Soup::new(html);
+    let document = soup.get_handle();
+    let width = width.unwrap_or(DEFAULT_WIDTH);
+
+    for noscript in soup.tag("noscript").find_all().collect::<Vec<_>>() {
+        unwrap_noscript(&noscript);
+    }
+
+    for img in soup.tag("img").find_all().collect::<Vec<_>>() {
+        let size = |attr: &str| img.get(attr).and_then(|size| size.trim().trim_end_matches("px").parse::<u32>().ok());
+        let is_pixel = size("width").is_some_and(|w| w <= 2) && size("height").is_some_and(|h| h <= 2);
+
+        match best_source(&img, width) {
+            Some(src) if !is_pixel => set_attr(&img, "src", base_url.join(&src).map(String::from).unwrap_or(src)),
+            _ => rules::detach(&img),
+        }
+    }
+
+    let mut resolved = Vec::new();
+    match html5ever::serialize(&mut resolved, &document, Default::default()) {
+        Ok(()) => String::from_utf8_lossy(&resolved).into_owned(),
+        Err(e) => {
+            warn!("Couldn't resolve image addresses: {}", e);
+            html.to_string()
+        },
+    }
+}
+
+// Put the image of a `<noscript>` fallback in its place, instead of the placeholder before it
+fn unwrap_noscript(noscript: &Handle) {
+    // html5ever parses with scripting enabled, like browsers, so the content is raw text
+    let fallback = Soup::new(&noscript.text());
+    let imgs: Vec<Handle> = fallback.tag("img").find_all().collect();
+    let parent = match (imgs.as_slice(), noscript.parent()) {
+        ([_], Some(parent)) => parent,
+        _ => return,  // Not an image fallback
+    };
+    let img = imgs[0].clone();
+
+    let mut children = parent.children.borrow_mut();
+    let position = match children.iter().position(|child| Rc::ptr_eq(child, noscript)) {
+        Some(position) => position,
+        None => return,
+    };
+    img.parent.set(Some(Rc::downgrade(&parent)));
+    children[position] = img;
+
+    let placeholder = children[..position].iter().rposition(|child| child.is_element() || !child.text().trim().is_empty());
+    if let Some(placeholder) = placeholder {
+        if children[placeholder].is_element() && children[placeholder].name() == "img" {
+            children.remove(placeholder);
+        }
+    }
+}
+
+// Address of the image, among the candidates of `img` and of its `<picture>`, closest
+// to `width` without being smaller, or the largest. Without sizes, the first found.
+fn best_source(img: &Handle, width: u32) -> Option<String> {
+    let base_width = img.get("width").and_then(|w| w.trim().parse::<u32>().ok()).unwrap_or(CSS_WIDTH);
+    let mut candidates: Vec<(String, Option<u32>)> = Vec::new();
+
+    let sources: Vec<_> = img.parent()
+        .filter(|parent| parent.is_element() && parent.name() == "picture")
+        .map(|picture| picture.children().filter(|child| child.is_element() && child.name() == "source").collect())
+        .unwrap_or_default();
+    for node in sources.iter().chain(std::iter::once(img)) {
+        for attr in LAZY_SRCSETS {
+            if let Some(srcset) = node.get(attr) {
+                candidates.extend(parse_srcset(&srcset, base_width));
+            }
+        }
+    }
+    for attr in LAZY_SRCS {
+        if let Some(src) = img.get(attr) {
+            candidates.push((src.trim().to_string(), None));
+        }
+    }
+    candidates.retain(|(src, _)| !src.is_empty() && !src.to_lowercase().starts_with("data:"));
+
+    let sized = candidates.iter().filter_map(|(src, w)| w.map(|w| (src, w)));
+    let fitting = sized.clone().filter(|(_, w)| *w >= width).min_by_key(|(_, w)| *w);
+    let largest = sized.max_by_key(|(_, w)| *w);
+
+    fitting.or(largest).map(|(src, _)| src.clone())
+        .or_else(|| candidates.into_iter().next().map(|(src, _)| src))
+}
+
+// Addresses in `srcset` and their widths, `1x` being `base_width`. Addresses may
+// contain commas, they end at a space (or a comma right before it).
+fn parse_srcset(srcset: &str, base_width: u32) -> Vec<(String, Option<u32>)> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            return candidates
+        }
+
+        let (src, after) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+        let descriptor = if src.ends_with(',') {
+            rest = after;
+            ""
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            rest = &after[end..];
+            after[..end].trim()
+        };
+
+        let value = descriptor.get(..descriptor.len().saturating_sub(1)).and_then(|value| value.parse::<f32>().ok());
+        let width = match (value, descriptor.chars().last()) {
+            (Some(value), Some('w')) => Some(value as u32),
+            (Some(value), Some('x')) => Some((value * base_width as f32) as u32),
+            _ => None,
+        };
+        candidates.push((src.trim_end_matches(',').to_string(), width));
+    }
+}
+
+fn set_attr(node: &Handle, name: &str, value: String) {
+    if let NodeData::Element { attrs, .. } = &node.data {
+        let mut attrs = attrs.borrow_mut();
+        match attrs.iter_mut().find(|attr| &*attr.name.local == name) {
+            Some(attr) => attr.value = value.into(),
+            None => attrs.push(Attribute {
+                name: QualName::new(None, Namespace::from(""), LocalName::from(name)),
+                value: value.into(),
+            }),
+        }
+    }
+}
+
+/// Turn the image at `path` into a JPEG or PNG, whatever its format, and name it after it.
+/// Images `image` can't read go through ImageMagick if they're in one of `MAGICK_FORMATS`:
+/// AVIF, HEIC, but also lossless, transparent or animated WebP. Returns the new path,
+/// the original file is replaced.
+pub fn normalize(path: &Path) -> Result<PathBuf> {
+    let bytes = fs::read(path)?;
+
+    let decoded = image::guess_format(&bytes)
+        .and_then(|format| Ok((image::load_from_memory_with_format(&bytes, format)?, format)));
+    let (image, format, bytes) = match decoded {
+        Ok((image, format)) => (image, format, bytes),
+        Err(e) => {
+            let magick_format = match magick_format(&bytes) {
+                Some(magick_format) => magick_format,
+                None => bail!("can't decode {} ({})", path.display(), e),
+            };
+            debug!("Converting {} with ImageMagick: {}", path.display(), e);
+
+            let png = rasterize(magick_format, &bytes)
+                .chain_err(|| format!("can't decode {} ({})", path.display(), e))?;
+            (image::load_from_memory_with_format(&png, ImageFormat::Png)?, ImageFormat::Png, png)
+        },
+    };
+
+    let (bytes, ext) = match format {
+        ImageFormat::Jpeg => (bytes, "jpg"),  // Already fine
+        ImageFormat::Png => (bytes, "png"),
+        // Only the first frame of animations, GIFs are mostly drawings
+        _ => encode(&image, format == ImageFormat::Gif || image.color().has_alpha())?,
+    };
+
+    let normalized = path.with_extension(ext);
+    fs::write(&normalized, bytes)?;
+    if normalized != path {
+        fs::remove_file(path)?;
+    }
+
+    Ok(normalized)
+}
+
+/// Fit the image at `path`, already normalized, to `device`: shrink it to the screen and,
+/// for e-ink, make it grey, darken mid tones and bring it to the screen's grey levels, as PNG.
+/// Photos are dithered, so gradients don't turn into bands; drawings are only rounded to the
+/// nearest level, which keeps their flat areas clean. Returns the new path.
+pub fn optimize(path: &Path, device: &Device) -> Result<PathBuf> {
+    let image = image::open(path)?;
+    let fits = image.width() <= device.width && image.height() <= device.height;
+    let image = if fits { image } else { image.resize(device.width, device.height, FilterType::Lanczos3) };
+
+    let (bytes, ext) = if device.grey {
+        let grey = on_white(&image);
+        let drawing = is_drawing(&grey);
+        let grey = darken(&grey);
+
+        let levels = if drawing { posterize(&grey) } else { dither(&grey) };
+        let mut bytes = Vec::new();
+        DynamicImage::ImageLuma8(levels).write_to(&mut bytes, ImageOutputFormat::Png)?;
+        (bytes, "png")
+    } else if fits {
+        return Ok(path.to_path_buf())  // Nothing to gain
+    } else {
+        encode(&image, path.extension().is_some_and(|ext| ext == "png"))?
+    };
+
+    let optimized = path.with_extension(ext);
+    fs::write(&optimized, bytes)?;
+    if optimized != path {
+        fs::remove_file(path)?;
+    }
+
+    Ok(optimized)
+}
+
+/// Media type of an image normalized by `normalize`.
+pub fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => "image/png",
+        _ => "image/jpeg",
+    }
+}
+
+// PNG for drawings and transparent images, JPEG for photos
+fn encode(image: &DynamicImage, lossless: bool) -> Result<(Vec<u8>, &'static str)> {
+    let mut bytes = Vec::new();
+    if lossless {
+        image.write_to(&mut bytes, ImageOutputFormat::Png)?;
+        return Ok((bytes, "png"))
+    }
+
+    // JPEG has neither alpha nor 16 bits channels
+    DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut bytes, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+    Ok((bytes, "jpg"))
+}
+
+// Grey version of `image`, transparent parts white like the page
+fn on_white(image: &DynamicImage) -> GrayImage {
+    let rgba = image.to_rgba8();
+    GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let luma = 0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b);
+        let alpha = f32::from(a) / 255.0;
+        Luma([(luma * alpha + 255.0 * (1.0 - alpha)).round() as u8])
+    })
+}
+
+fn darken(grey: &GrayImage) -> GrayImage {
+    let mut table = [0u8; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+        *entry = (255.0 * (value as f32 / 255.0).powf(E_INK_GAMMA)).round() as u8;
+    }
+
+    let mut darker = grey.clone();
+    for pixel in darker.pixels_mut() {
+        pixel.0[0] = table[pixel.0[0] as usize];
+    }
+    darker
+}
+
+// Drawings, diagrams and screenshots are mostly flat areas of a few tones, unlike photos
+fn is_drawing(grey: &GrayImage) -> bool {
+    let mut histogram = [0u64; 256];
+    for pixel in grey.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    histogram.sort_unstable_by(|a, b| b.cmp(a));
+
+    let main_tones: u64 = histogram[..GREY_LEVELS as usize].iter().sum();
+    main_tones as f64 >= DRAWING_SHARE * (u64::from(grey.width()) * u64::from(grey.height())) as f64
+}
+
+// Grey level of e-ink nearest to `value`
+fn nearest_level(value: f32) -> f32 {
+    let step = 255.0 / f32::from(GREY_LEVELS - 1);
+    ((value / step).round() * step).clamp(0.0, 255.0)
+}
+
+fn posterize(grey: &GrayImage) -> GrayImage {
+    let mut levels = grey.clone();
+    for pixel in levels.pixels_mut() {
+        pixel.0[0] = nearest_level(f32::from(pixel.0[0])).round() as u8;
+    }
+    levels
+}
+
+// Floyd–Steinberg dithering to the grey levels of e-ink
+fn dither(grey: &GrayImage) -> GrayImage {
+    let (width, height) = (grey.width() as usize, grey.height() as usize);
+    let mut values: Vec<f32> = grey.pixels().map(|pixel| f32::from(pixel.0[0])).collect();
+
+    for y in 0..height {
+        for x in 0..width {
+            let old = values[y * width + x];
+            let new = nearest_level(old);
+            values[y * width + x] = new;
+
+            let error = old - new;
+            let mut spread = |dx: isize, dy: usize, share: f32| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    values[(y + dy) * width + nx as usize] += error * share;
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+
+    GrayImage::from_fn(width as u32, height as u32, |x, y| Luma([values[y as usize * width + x as usize].round() as u8]))
+}
+
+// PNG rendering of the image at `path`, in the ImageMagick `format` if known
+// PNG of the image in `bytes`, in the ImageMagick `format`
+fn rasterize(format: &str, bytes: &[u8]) -> Result<Vec<u8>> {
+    if !MAGICK_FORMATS.contains(&format) {
+        bail!("ImageMagick isn't trusted with {} images", format)
+    }
+
+    let mut child = ImageMagickCmd::to_png(format)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .chain_err(|| "Couldn't run convert, is ImageMagick installed?")?;
+
+    // Fed from a thread of its own, convert may fill its output before reading all of it
+    let mut stdin = child.stdin.take().unwrap();
+    let input = bytes.to_vec();
+    let feeder = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let _ = feeder.join();
+
+    Ok(cmd::check_output("convert", output)?.stdout)
+}
+
+// ImageMagick coder for the image in `bytes`, told by its signature, if it's one of
+// `MAGICK_FORMATS`. Others are refused: coders like MVG, MSL, SVG or PS read other files,
+// URLs or run programs, which pages we download must not make ImageMagick do.
+fn magick_format(bytes: &[u8]) -> Option<&'static str> {
+    // ISO base media file, e.g. `....ftypavif`
+    let brand = bytes.get(4..12).filter(|header| header.starts_with(b"ftyp")).map(|header| &header[4..]);
+
+    match bytes {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => Some("tiff"),
+        [b'B', b'M', ..] => Some("bmp"),
+        [0, 0, 1, 0, ..] => Some("ico"),
+        _ => match brand? {
+            b"avif" | b"avis" => Some("avif"),
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"mif1" | b"msf1" => Some("heic"),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_trusts_known_formats_to_imagemagick() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"RIFF\x24\0\0\0WEBPVP8L", Some("webp")),
+            (b"\0\0\0\x1cftypavif\0\0\0\0", Some("avif")),
+            (b"\0\0\0\x18ftypheic\0\0\0\0", Some("heic")),
+            (b"II*\0\x08\0\0\0", Some("tiff")),
+            (b"BM\x36\0\0\0", Some("bmp")),
+            (b"\0\0\x01\0\x01\0", Some("ico")),
+            (b"<svg xmlns=\"http://www.w3.org/2000/svg\"><image href=\"file:///etc/passwd\"/></svg>", None),
+            (b"push graphic-context\nimage over 0,0 0,0 'https://example.com/x.png'", None),
+            (b"%!PS-Adobe-3.0\n", None),
+            (b"<?xml version=\"1.0\"?><image><read filename=\"/etc/passwd\"/></image>", None),
+            (b"\0\0\0\x18ftypisom\0\0\0\0", None),
+            (b"RIFF", None),
+        ];
+
+        for (bytes, format) in cases {
+            assert_eq!(magick_format(bytes), *format, "{}", String::from_utf8_lossy(bytes));
+        }
+        assert!(rasterize("svg", b"<svg/>").is_err());
+    }
+}
@@ -0,0 +1,533 @@
+use std::io::Read;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+
+use crate::cmd::{check_output, CalibreCmd};
+use crate::config;
+use crate::download::Task;
+use crate::naming::{self, NameFields};
+use crate::web::Article;
+use crate::errors::*;
+use crate::logging;
+
+// How often a running child is checked for exit or cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Time a cancelled program gets to terminate before it's killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub type JobId = usize;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobSource {
+    File(PathBuf),  // Local file to convert and send
+    Url(String),  // Article to download as EPUB
+}
+
+impl JobSource {
+    pub fn name(&self) -> String {
+        match self {
+            JobSource::File(file) => file.file_name().unwrap_or_default().to_string_lossy().into(),
+            JobSource::Url(url) => url.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobState {
+    Queued,
+    Fetching,
+    Converting,
+    Sending,
+    Saved,  // Article written to disk, not sent
+    Done,
+    Failed(Report),
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Saved | JobState::Done | JobState::Failed(_) | JobState::Cancelled)
+    }
+
+    /// Stable identifier, for other applications.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Fetching => "fetching",
+            JobState::Converting => "converting",
+            JobState::Sending => "sending",
+            JobState::Saved => "saved",
+            JobState::Done => "done",
+            JobState::Failed(_) => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            JobState::Queued => "Queued".into(),
+            JobState::Fetching => "Downloading...".into(),
+            JobState::Converting => "Converting...".into(),
+            JobState::Sending => "Sending...".into(),
+            JobState::Saved => "Saved".into(),
+            JobState::Done => "Sent".into(),
+            JobState::Failed(_) => "Failed".into(),
+            JobState::Cancelled => "Cancelled".into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JobEvent {
+    pub id: JobId,
+    pub source: JobSource,
+    pub output: Option<PathBuf>,  // File produced by the job, once known
+    pub cfg: HashMap<String, String>,  // Config the job runs with, to retry it the same way
+    pub batch: Vec<JobId>,  // Jobs submitted together with this one, itself included
+    pub state: JobState,
+    pub progress: Option<(usize, usize)>,  // Files done and total in the current state, if counted
+}
+
+impl JobEvent {
+    /// State with its progress, e.g. "Downloading images 3/10...".
+    pub fn describe(&self) -> String {
+        match (&self.state, self.progress) {
+            (JobState::Fetching, Some((done, total))) => format!("Downloading images {}/{}...", done, total),
+            (state, _) => state.describe(),
+        }
+    }
+}
+
+struct Job {
+    id: JobId,
+    source: JobSource,
+    output: Option<PathBuf>,  // File to mail after conversion, or the downloaded article
+    cfg: HashMap<String, String>,  // Config snapshot taken when the job was queued
+    batch: Vec<JobId>,
+    cancelled: Arc<AtomicBool>,
+    #[allow(dead_code)]  // Only kept until the job is dropped
+    scratch: Option<TempDir>,  // Deleted when the job is over, with the job
+}
+
+type Listener = Box<dyn Fn(&JobEvent) + Send>;
+
+struct Shared {
+    listeners: Mutex<Vec<Listener>>,
+    live: Mutex<HashMap<JobId, Arc<AtomicBool>>>,  // Cancel flags of unfinished jobs
+    latest: Mutex<BTreeMap<JobId, JobEvent>>,  // Last known state of every job
+}
+
+impl Shared {
+    fn emit(&self, job: &Job, state: JobState) {
+        logging::set_current_job(Some(job.id));
+        match &state {
+            JobState::Failed(report) => error!("{}", report.details),
+            _ => info!("{}: {}", job.source.name(), state.describe()),
+        }
+
+        if state.is_finished() {
+            self.live.lock().unwrap().remove(&job.id);
+        }
+
+        let event = JobEvent {
+            id: job.id,
+            source: job.source.clone(),
+            output: job.output.clone(),
+            cfg: job.cfg.clone(),
+            batch: job.batch.clone(),
+            state,
+            progress: None,
+        };
+
+        self.latest.lock().unwrap().insert(job.id, event.clone());
+        self.notify(&event);
+    }
+
+    // Same state, `done` of `total` files further. Too frequent to log.
+    fn progress(&self, id: JobId, done: usize, total: usize) {
+        let event = match self.latest.lock().unwrap().get_mut(&id) {
+            Some(event) if !event.state.is_finished() => {
+                event.progress = Some((done, total));
+                event.clone()
+            },
+            _ => return,
+        };
+        self.notify(&event);
+    }
+
+    fn notify(&self, event: &JobEvent) {
+        for listener in self.listeners.lock().unwrap().iter() {
+            listener(event);
+        }
+    }
+}
+
+/// Bounded pool of conversion (and article download) workers feeding a single
+/// sending worker, so that mails go out while the next files are still being converted.
+#[derive(Clone)]
+pub struct JobPool {
+    next_id: Arc<AtomicUsize>,
+    convert_tx: mpsc::Sender<Job>,
+    shared: Arc<Shared>,
+}
+
+impl JobPool {
+    pub fn new(parallelism: usize) -> Self {
+        let shared = Arc::new(Shared {
+            listeners: Mutex::new(Vec::new()),
+            live: Mutex::new(HashMap::new()),
+            latest: Mutex::new(BTreeMap::new()),
+        });
+
+        let (convert_tx, convert_rx) = mpsc::channel::<Job>();
+        let (send_tx, send_rx) = mpsc::channel::<Job>();
+        let convert_rx = Arc::new(Mutex::new(convert_rx));
+
+        // Conversion workers
+        for _ in 0..parallelism.max(1) {
+            let convert_rx = Arc::clone(&convert_rx);
+            let send_tx = send_tx.clone();
+            let shared = Arc::clone(&shared);
+
+            thread::spawn(move || loop {
+                // Lock is released at the end of the statement, before converting
+                let job = convert_rx.lock().unwrap().recv();
+                let job = match job {
+                    Ok(job) => job,
+                    Err(_) => break,  // Pool dropped
+                };
+
+                let job = match job.source {
+                    JobSource::File(_) => convert_job(job, &shared),
+                    JobSource::Url(_) => fetch_job(job, &shared),
+                };
+                logging::set_current_job(None);
+
+                if let Some(job) = job {
+                    if send_tx.send(job).is_err() {
+                        break
+                    }
+                }
+            });
+        }
+
+        // Sending worker
+        let send_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            for job in send_rx {
+                send_job(job, &send_shared);
+                logging::set_current_job(None);
+            }
+        });
+
+        Self {
+            next_id: Arc::new(AtomicUsize::new(1)),
+            convert_tx,
+            shared,
+        }
+    }
+
+    /// Register a callback for job state changes. It runs on worker threads.
+    pub fn connect_event<F: Fn(&JobEvent) + Send + 'static>(&self, f: F) {
+        self.shared.listeners.lock().unwrap().push(Box::new(f));
+    }
+
+    pub fn submit(&self, source: JobSource, cfg: HashMap<String, String>) -> JobId {
+        self.submit_batch(vec![(source, cfg)])[0]
+    }
+
+    /// Like `submit`, deleting `dir` and everything in it once the job is over,
+    /// e.g. the folder holding an uploaded file.
+    pub fn submit_in(&self, source: JobSource, cfg: HashMap<String, String>, dir: TempDir) -> JobId {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.enqueue(id, source, cfg, vec![id], Some(dir));
+        logging::set_current_job(None);
+        id
+    }
+
+    /// Submit jobs that belong together, e.g. files sent at once.
+    /// Listeners get the ids of the whole batch with every event.
+    pub fn submit_batch(&self, jobs: Vec<(JobSource, HashMap<String, String>)>) -> Vec<JobId> {
+        let first = self.next_id.fetch_add(jobs.len(), Ordering::SeqCst);
+        let batch: Vec<JobId> = (first..first + jobs.len()).collect();
+
+        for (id, (source, cfg)) in batch.iter().copied().zip(jobs) {
+            self.enqueue(id, source, cfg, batch.clone(), None);
+        }
+        logging::set_current_job(None);
+
+        batch
+    }
+
+    fn enqueue(&self, id: JobId, source: JobSource, cfg: HashMap<String, String>, batch: Vec<JobId>, scratch: Option<TempDir>) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.shared.live.lock().unwrap().insert(id, Arc::clone(&cancelled));
+
+        let job = Job {
+            id,
+            source,
+            output: None,  // Named once converted or downloaded
+            cfg,
+            batch,
+            cancelled,
+            scratch,
+        };
+
+        self.shared.emit(&job, JobState::Queued);
+        if let Err(mpsc::SendError(job)) = self.convert_tx.send(job) {
+            let e: Error = "worker pool stopped".into();
+            self.shared.emit(&job, JobState::Failed(Report::from(&e)));
+        }
+    }
+
+    /// Cancel a job, killing its running process if any.
+    /// Returns `false` if there is no such unfinished job.
+    pub fn cancel(&self, id: JobId) -> bool {
+        match self.shared.live.lock().unwrap().get(&id) {
+            Some(flag) => {
+                flag.store(true, Ordering::SeqCst);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Last known state of every job, oldest first.
+    pub fn list(&self) -> Vec<JobEvent> {
+        self.shared.latest.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn cancel_all(&self) {
+        for flag in self.shared.live.lock().unwrap().values() {
+            flag.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+fn fetch_job(mut job: Job, shared: &Arc<Shared>) -> Option<Job> {
+    if job.cancelled.load(Ordering::SeqCst) {
+        shared.emit(&job, JobState::Cancelled);
+        return None
+    }
+
+    shared.emit(&job, JobState::Fetching);
+    let url = match &job.source {
+        JobSource::Url(url) => url.clone(),
+        JobSource::File(_) => return Some(job),
+    };
+
+    let progress_shared = Arc::clone(shared);
+    let id = job.id;
+    let task = Task::new(Arc::clone(&job.cancelled))
+        .on_progress(move |done, total| progress_shared.progress(id, done, total));
+    let epub_path = match Article::epub_from_url(url, &job.cfg, &task) {
+        result if job.cancelled.load(Ordering::SeqCst) => {
+            if let Ok(epub_path) = result {
+                let _ = fs::remove_file(epub_path);
+            }
+            shared.emit(&job, JobState::Cancelled);
+            return None
+        },
+        Ok(epub_path) => epub_path,
+        Err(e) => {
+            shared.emit(&job, JobState::Failed(Report::from(&e)));
+            return None
+        },
+    };
+
+    if !job.cfg.get("send_articles").unwrap().parse().unwrap_or(false) {
+        job.output = Some(epub_path);
+        shared.emit(&job, JobState::Saved);
+        return None  // Download only
+    }
+
+    // On to the sending worker, like any other file, keeping the article name
+    let out_dir = epub_path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let stem = epub_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    convert(job, &epub_path, &out_dir, &stem, shared)
+}
+
+fn convert_job(job: Job, shared: &Shared) -> Option<Job> {
+    if job.cancelled.load(Ordering::SeqCst) {
+        shared.emit(&job, JobState::Cancelled);
+        return None
+    }
+
+    let file = match &job.source {
+        JobSource::File(file) => file.clone(),
+        JobSource::Url(_) => return None,
+    };
+
+    // Converted files go next to their source, unless an output folder is set
+    let out_dir = match job.cfg.get("output_dir") {
+        Some(dir) if !dir.trim().is_empty() => config::output_dir(&job.cfg),
+        _ => file.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    let title = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let stem = naming::file_stem(job.cfg.get("name_template").unwrap(), &NameFields {
+        title: Some(&title),
+        ..NameFields::default()
+    });
+
+    convert(job, &file, &out_dir, &stem, shared)
+}
+
+// Convert `file` into `<out_dir>/<stem>.<to_ext>`, or a free variation of it, ready for sending
+fn convert(mut job: Job, file: &Path, out_dir: &Path, stem: &str, shared: &Shared) -> Option<Job> {
+    if !file.exists() {
+        let e: Error = format!("'{}' not found", file.display()).into();
+        shared.emit(&job, JobState::Failed(Report::from(&e)));
+        return None
+    }
+
+    let to_ext = job.cfg.get("to_ext").unwrap().clone();
+    if file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(&to_ext)) {
+        job.output = Some(file.to_path_buf());
+        return Some(job)  // Conversion unnecessary
+    }
+
+    let output = match naming::reserve(out_dir, stem, &to_ext) {
+        Ok(output) => output,
+        Err(e) => {
+            let e = Error::from(e).chain_err(|| ErrorKind::Conversion(file.display().to_string()));
+            shared.emit(&job, JobState::Failed(Report::from(&e)));
+            return None
+        },
+    };
+    job.output = Some(output.clone());
+
+    shared.emit(&job, JobState::Converting);
+    let cmd = CalibreCmd::convert(file, &output);
+
+    let result = run_cancellable("ebook-convert", cmd, &job.cancelled)
+        .chain_err(|| ErrorKind::Conversion(file.display().to_string()));
+
+    match result {
+        Ok(Some(_)) => Some(job),
+        Ok(None) => {
+            let _ = fs::remove_file(&output);
+            shared.emit(&job, JobState::Cancelled);
+            None
+        },
+        Err(e) => {
+            let _ = fs::remove_file(&output);
+            shared.emit(&job, JobState::Failed(Report::from(&e)));
+            None
+        },
+    }
+}
+
+fn send_job(job: Job, shared: &Shared) {
+    if job.cancelled.load(Ordering::SeqCst) {
+        shared.emit(&job, JobState::Cancelled);
+        return
+    }
+
+    let attachment = match &job.output {
+        Some(attachment) => attachment.clone(),
+        None => return,
+    };
+
+    shared.emit(&job, JobState::Sending);
+    let cmd = CalibreCmd::send(&attachment, &job.cfg);
+
+    let result = run_cancellable("calibre-smtp", cmd, &job.cancelled)
+        .chain_err(|| ErrorKind::Sending(attachment.display().to_string()));
+
+    match result {
+        Ok(Some(_)) => {
+            // Delete sent file?
+            if job.cfg.get("del_sent").unwrap().parse().unwrap_or(false) {
+                if let JobSource::File(file) = &job.source {
+                    let _del_result = fs::remove_file(file);
+                }
+            }
+            shared.emit(&job, JobState::Done);
+        },
+        Ok(None) => shared.emit(&job, JobState::Cancelled),
+        Err(e) => shared.emit(&job, JobState::Failed(Report::from(&e))),
+    }
+}
+
+/// Run `cmd` to completion, stopping it and its subprocesses as soon as `cancelled` is set.
+/// Returns `None` if the process was stopped, a `Tool` error if it failed.
+fn run_cancellable(program: &str, mut cmd: Command, cancelled: &AtomicBool) -> Result<Option<Output>> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    cmd.process_group(0);  // A group of its own, shared with the workers it starts
+    let mut child = cmd
+        .spawn()
+        .chain_err(|| format!("Couldn't run {}, is Calibre installed?", program))?;
+
+    // Drain pipes on their own threads, a full pipe would block the child forever
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            let output = Output {
+                status,
+                stdout: stdout.join().unwrap_or_default(),
+                stderr: stderr.join().unwrap_or_default(),
+            };
+            debug!("{} output:\n{}", program, String::from_utf8_lossy(&output.stdout));
+            return check_output(program, output).map(Some)
+        }
+
+        if cancelled.load(Ordering::SeqCst) {
+            info!("Stopping {}", program);
+            stop_group(&mut child)?;
+            return Ok(None)
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Ask the process group of `child` to terminate, then kill what is left of it.
+// Calibre converts in worker processes, which would outlive their parent.
+#[cfg(unix)]
+fn stop_group(child: &mut Child) -> Result<()> {
+    let group = -(child.id() as libc::pid_t);
+    // Only sends a signal, failing harmlessly if the group is gone already
+    unsafe { libc::kill(group, libc::SIGTERM) };
+
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while child.try_wait()?.is_none() && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    child.wait()?;
+    Ok(())
+}
+
+// Without process groups only `child` itself can be stopped
+#[cfg(not(unix))]
+fn stop_group(child: &mut Child) -> Result<()> {
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
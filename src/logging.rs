@@ -0,0 +1,248 @@
+use std::io::Write;
+use std::fs;
+use std::env;
+use std::path::PathBuf;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use regex::Regex;
+
+use crate::jobs::JobId;
+
+const LOG_NAME: &str = "kindle-pult.log";
+const MAX_LOG_SIZE: u64 = 1024 * 1024;  // Rotate after 1 MiB
+const KEPT_LOGS: usize = 3;  // kindle-pult.log.1 ... kindle-pult.log.3
+const KEPT_ENTRIES: usize = 2000;  // In memory, for the log viewer
+
+lazy_static! {
+    static ref LOGGER: PultLogger = PultLogger::new();
+
+    static ref EMAIL: Regex = Regex::new(
+        r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"
+    ).unwrap();
+
+    // `password=...`, `"token": "..."`, `api_key: ...` and friends
+    static ref KEY_VALUE: Regex = Regex::new(
+        r#"(?i)\b((?:password|passwd|pwd|token|access_token|secret|api[_-]?key|auth)["']?\s*[:=]\s*["']?)[^\s"'&,;]+"#
+    ).unwrap();
+
+    static ref AUTH_HEADER: Regex = Regex::new(
+        r"(?i)\b(authorization:\s*(?:bearer|basic)?\s*)\S+"
+    ).unwrap();
+}
+
+thread_local! {
+    // Job the current thread is working on, attached to every record it logs
+    static CURRENT_JOB: Cell<Option<JobId>> = const { Cell::new(None) };
+}
+
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub time: String,
+    pub level: Level,
+    pub job: Option<JobId>,
+    pub message: String,  // Already redacted
+}
+
+impl LogEntry {
+    pub fn format(&self) -> String {
+        match self.job {
+            Some(job) => format!("{} {:<5} [job {}] {}", self.time, self.level, job, self.message),
+            None => format!("{} {:<5} {}", self.time, self.level, self.message),
+        }
+    }
+}
+
+type Listener = Box<dyn Fn(&LogEntry) + Send>;
+
+struct PultLogger {
+    file: Mutex<Option<fs::File>>,
+    entries: Mutex<VecDeque<LogEntry>>,
+    listeners: Mutex<Vec<Listener>>,
+    secrets: Mutex<Vec<String>>,  // Literal values to mask, e.g. the SMTP password
+}
+
+impl PultLogger {
+    fn new() -> Self {
+        Self {
+            file: Mutex::new(None),
+            entries: Mutex::new(VecDeque::new()),
+            listeners: Mutex::new(Vec::new()),
+            secrets: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn write_to_file(&self, line: &str) {
+        let mut file = self.file.lock().unwrap();
+
+        let too_big = file.as_ref()
+            .and_then(|f| f.metadata().ok())
+            .is_some_and(|m| m.len() > MAX_LOG_SIZE);
+        if too_big {
+            *file = rotate().ok();
+        }
+
+        if let Some(f) = file.as_mut() {
+            let _ = writeln!(f, "{}", line);
+        }
+    }
+}
+
+impl Log for PultLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+
+        let entry = LogEntry {
+            time: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            level: record.level(),
+            job: CURRENT_JOB.with(|job| job.get()),
+            message: redact(&record.args().to_string()),
+        };
+
+        self.write_to_file(&entry.format());
+
+        for listener in self.listeners.lock().unwrap().iter() {
+            listener(&entry);
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == KEPT_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    fn flush(&self) {
+        if let Some(f) = self.file.lock().unwrap().as_mut() {
+            let _ = f.flush();
+        }
+    }
+}
+
+/// Install the logger. Level defaults to `info`, override with `KINDLE_PULT_LOG`.
+pub fn init() {
+    let level = env::var("KINDLE_PULT_LOG").ok()
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(LevelFilter::Info);
+
+    match open_log_file() {
+        Ok(file) => *LOGGER.file.lock().unwrap() = Some(file),
+        Err(e) => eprintln!("Logging to file disabled: {}", e),
+    }
+
+    if log::set_logger(&*LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// `$XDG_STATE_HOME/kindle-pult`, defaulting to `~/.local/state/kindle-pult`.
+pub fn log_dir() -> PathBuf {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .unwrap_or_else(env::temp_dir);
+
+    state_home.join("kindle-pult")
+}
+
+fn open_log_file() -> std::io::Result<fs::File> {
+    let dir = log_dir();
+    fs::create_dir_all(&dir)?;
+    fs::OpenOptions::new().create(true).append(true).open(dir.join(LOG_NAME))
+}
+
+// Shift kindle-pult.log.N up by one, dropping the oldest, and start a new log
+fn rotate() -> std::io::Result<fs::File> {
+    let dir = log_dir();
+    for n in (1..KEPT_LOGS).rev() {
+        let _ = fs::rename(dir.join(format!("{}.{}", LOG_NAME, n)), dir.join(format!("{}.{}", LOG_NAME, n + 1)));
+    }
+    fs::rename(dir.join(LOG_NAME), dir.join(format!("{}.1", LOG_NAME)))?;
+
+    open_log_file()
+}
+
+/// Attach `job` to the records logged by the current thread until reset with `None`.
+pub fn set_current_job(job: Option<JobId>) {
+    CURRENT_JOB.with(|current| current.set(job));
+}
+
+/// Mask this literal value in every following record.
+pub fn add_secret(secret: &str) {
+    if secret.len() < 3 {
+        return  // Masking very short values would garble everything
+    }
+
+    let mut secrets = LOGGER.secrets.lock().unwrap();
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.into());
+    }
+}
+
+/// Snapshot of the most recent records, oldest first.
+pub fn entries() -> Vec<LogEntry> {
+    LOGGER.entries.lock().unwrap().iter().cloned().collect()
+}
+
+/// Register a callback for new records. It runs on the logging thread.
+pub fn connect_entry<F: Fn(&LogEntry) + Send + 'static>(f: F) {
+    LOGGER.listeners.lock().unwrap().push(Box::new(f));
+}
+
+/// `message` as it's logged: secrets, credentials and email addresses masked.
+pub fn redact(message: &str) -> String {
+    redact_with(message, &LOGGER.secrets.lock().unwrap())
+}
+
+fn redact_with(message: &str, secrets: &[String]) -> String {
+    let mut message = message.to_string();
+    for secret in secrets {
+        message = message.replace(secret.as_str(), "<redacted>");
+    }
+
+    let message = AUTH_HEADER.replace_all(&message, "${1}<redacted>");
+    let message = KEY_VALUE.replace_all(&message, "${1}<redacted>");
+    EMAIL.replace_all(&message, "<email>").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_credentials_and_addresses() {
+        let cases = [
+            ("login password=hunter2 ok", "login password=<redacted> ok"),
+            (r#"{"token": "abc123", "id": 4}"#, r#"{"token": "<redacted>", "id": 4}"#),
+            ("GET /api/jobs?token=abc123&x=1", "GET /api/jobs?token=<redacted>&x=1"),
+            ("Authorization: Bearer abc.def", "Authorization: Bearer <redacted>"),
+            ("sent to me.name+kindle@example.co.uk", "sent to <email>"),
+            ("nothing to hide", "nothing to hide"),
+        ];
+        for (message, redacted) in cases.iter() {
+            assert_eq!(redact_with(message, &[]), *redacted);
+        }
+
+        let secrets = ["s3cr3t pass".to_string()];
+        assert_eq!(redact_with("smtp failed with s3cr3t pass twice", &secrets), "smtp failed with <redacted> twice");
+    }
+
+    #[test]
+    fn masks_added_secrets() {
+        add_secret("xy");  // Too short to mask
+        add_secret("long-secret-value");
+        add_secret("long-secret-value");
+
+        assert_eq!(redact("xy long-secret-value"), "xy <redacted>");
+        assert_eq!(LOGGER.secrets.lock().unwrap().iter().filter(|s| *s == "long-secret-value").count(), 1);
+    }
+}
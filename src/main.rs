@@ -3,25 +3,130 @@ extern crate gio;
 extern crate gtk;
 use gio::prelude::*;
 use std::env::args;
+use std::rc::Rc;
 
 #[macro_use]
 extern crate error_chain;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate lazy_static;
 
+mod errors;
 mod gui;
 mod web;
+mod charset;
 mod cmd;
 mod config;
+mod cookies;
+#[cfg(unix)]
+mod dbus;
+mod download;
+mod extract;
+mod images;
+mod jobs;
+mod logging;
+mod metadata;
+mod naming;
+mod native;
+mod notify;
+mod readability;
+mod remote;
+mod rules;
+mod server;
+mod uri;
+mod xhtml;
 
 use crate::gui::Gui;
+use crate::remote::{Arg, Invocation, Request};
 
 fn main() {
-    if gtk::init().is_err() { println!("Failed to initialize GTK."); return; }
-    let application = gtk::Application::new(Some("kindle-pult.zwitterio.it"), Default::default())
-    .expect("Initialization failed...");
+    logging::init();
+
+    // Check arguments here, the running instance can't print to our terminal
+    let argv: Vec<std::ffi::OsString> = std::env::args_os().skip(1).collect();
+    if native::is_host_invocation(&argv) {
+        if let Err(e) = native::run() {
+            error!("Native messaging: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    match remote::parse_args(&argv) {
+        Ok(parsed) if parsed.help => { println!("{}", remote::USAGE); return; },
+        Ok(_) => (),
+        Err(e) => { eprintln!("{}\n\n{}", e, remote::USAGE); std::process::exit(2); },
+    }
 
-    application.connect_activate(move |app| {
-        let gui = Gui::new(app);
+    if gtk::init().is_err() { error!("Failed to initialize GTK."); return; }
+    // Single instance: later launches hand their command line over to the first one.
+    // Desktop launches (`Exec=kindle-pult %U`) come as command lines too, file URIs included.
+    let application = gtk::Application::new(
+        Some("kindle-pult.zwitterio.it"),
+        gio::ApplicationFlags::HANDLES_COMMAND_LINE,
+    ).expect("Initialization failed...");
+
+    // One window per application, whatever the entry point
+    application.connect_startup(|app| {
+        let gui = Rc::new(Gui::new(app));
         gui.build();
+
+        let gui_clone = Rc::clone(&gui);
+        app.connect_activate(move |_| gui_clone.present());
+
+        app.connect_command_line(move |_, cmdline| {
+            let invocations = match invocations_from(cmdline) {
+                Some(invocations) => invocations,
+                None => return 2,
+            };
+
+            gui.present();
+            let mut status = 0;
+            for invocation in invocations {
+                if let Err(e) = invocation.and_then(|invocation| gui.queue(invocation)) {
+                    gui.show_error(&e);
+                    status = 1;
+                }
+            }
+            status
+        });
     });
     application.run(&args().collect::<Vec<_>>());
 }
+
+// Resolve files against the directory of the (possibly remote) caller.
+// Links carry their own profile, so each makes an invocation of its own.
+fn invocations_from(cmdline: &gio::ApplicationCommandLine) -> Option<Vec<errors::Result<Invocation>>> {
+    let argv: Vec<std::ffi::OsString> = cmdline.get_arguments().into_iter().skip(1).collect();
+    let parsed = remote::parse_args(&argv).ok()?;
+    let path_of = |arg: &std::ffi::OsString| cmdline.create_file_for_arg(arg).and_then(|f| f.get_path());
+
+    let mut invocation = Invocation {
+        profile: parsed.profile,
+        requests: Vec::new(),
+    };
+    let mut links = Vec::new();
+
+    for arg in parsed.args {
+        let request = match arg {
+            Arg::Positional(arg) => match arg.to_str() {
+                Some(url) if remote::is_web_url(url) => Some(Request::Url(url.into())),
+                Some(link) if uri::is_pult_uri(link) => {
+                    links.push(uri::parse(link));
+                    continue
+                },
+                _ => path_of(&arg).map(Request::Open),
+            },
+            Arg::Send(arg) => path_of(&arg).map(Request::Send),
+            Arg::Url(url) => Some(Request::Url(url)),
+        };
+
+        match request {
+            Some(request) => invocation.requests.push(request),
+            None => warn!("Ignoring argument that is not a local file"),
+        }
+    }
+
+    Some(std::iter::once(Ok(invocation)).chain(links).collect())
+}
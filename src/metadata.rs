@@ -0,0 +1,217 @@
+use isolang::Language;
+use serde_json::Value;
+use soup::prelude::*;
+use url::Url;
+
+use crate::web::Article;
+
+// Description made from the text when the page has none, in characters
+const EXCERPT_LEN: usize = 300;
+// Text looked at to guess the language, more only slows detection down
+const DETECTION_LEN: usize = 10_000;
+
+/// What a page says about its article, from its JSON-LD, OpenGraph and Dublin Core
+/// tags and plain HTML, in this order of preference.
+#[derive(Debug, Default)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+    pub language: Option<String>,
+    pub site_name: Option<String>,
+    pub url: Option<String>,  // Canonical address
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+}
+
+pub fn from_html(html: &str, url: &Url) -> PageMetadata {
+    let soup = Soup::new(html);
+    let ld = json_ld(&soup).unwrap_or_default();
+    let metas = metas(&soup);
+    let meta = |names: &[&str]| names.iter()
+        .find_map(|name| metas.iter().find(|(n, _)| n == name))
+        .map(|(_, content)| content.clone());
+
+    let keywords = if !ld.keywords.is_empty() {
+        ld.keywords
+    } else {
+        // One tag per `article:tag`, or a comma separated list
+        let tags: Vec<String> = metas.iter().filter(|(n, _)| n == "article:tag").map(|(_, tag)| tag.clone()).collect();
+        if tags.is_empty() { split_list(&meta(&["keywords", "news_keywords", "dc.subject"]).unwrap_or_default()) } else { tags }
+    };
+
+    let canonical = soup.tag("link").find_all()
+        .find(|link| link.get("rel").is_some_and(|rel| rel.split_whitespace().any(|r| r.eq_ignore_ascii_case("canonical"))))
+        .and_then(|link| link.get("href"));
+    let html_lang = soup.tag("html").find().and_then(|html| html.get("lang").or_else(|| html.get("xml:lang")));
+
+    PageMetadata {
+        title: ld.title.or_else(|| meta(&["og:title", "twitter:title", "dc.title"])),
+        author: ld.author.or_else(|| meta(&["author", "article:author", "dc.creator"]).filter(|author| !author.starts_with("http"))),
+        date: ld.date.or_else(|| meta(&["article:published_time", "dc.date.issued", "dc.date", "date", "pubdate", "publish-date"])),
+        language: ld.language.or_else(|| meta(&["og:locale", "dc.language", "content-language"])).or(html_lang)
+            .and_then(|language| language_code(&language)),
+        site_name: ld.site_name.or_else(|| meta(&["og:site_name", "dc.publisher", "application-name"])),
+        url: ld.url.or(canonical).or_else(|| meta(&["og:url"]))
+            .and_then(|address| url.join(&address).ok())
+            .filter(|address| address.scheme() == "http" || address.scheme() == "https")
+            .map(String::from),
+        description: ld.description.or_else(|| meta(&["og:description", "description", "dc.description", "twitter:description"])),
+        keywords: dedup(keywords),
+    }
+}
+
+/// Fill in what `article` lacks from the metadata of its `html`, guessing the language
+/// from the text when the page doesn't tell. Author and date declared by the page are
+/// preferred to the ones engines find in the text.
+pub fn complete(article: &mut Article, html: &str, url: &Url) {
+    let page = from_html(html, url);
+    let text = article.content.as_deref().map(|content| Soup::new(content).text()).unwrap_or_default();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    article.title = article.title.take().or(page.title);
+    article.byline = page.author.or_else(|| article.byline.take());
+    article.date = page.date.or_else(|| article.date.take());
+    article.language = article.language.take().or(page.language).or_else(|| detect_language(&text));
+    article.site_name = article.site_name.take().or(page.site_name);
+    article.url = article.url.take().or(page.url);
+    article.excerpt = article.excerpt.take().or(page.description).or_else(|| excerpt(&text));
+    if article.keywords.is_empty() {
+        article.keywords = page.keywords;
+    }
+}
+
+/// ISO 639-1 code of the language `text` is written in, if reliably detected.
+pub fn detect_language(text: &str) -> Option<String> {
+    let sample: String = text.chars().take(DETECTION_LEN).collect();
+    let info = whatlang::detect(&sample).filter(|info| info.is_reliable())?;
+
+    language_code(info.lang().code())
+}
+
+// Primary language of a tag like `en-US`, `it_IT` or `eng`, as two letters when there are
+fn language_code(tag: &str) -> Option<String> {
+    let primary = tag.trim().split(['-', '_']).next()?.to_lowercase();
+    let language = match primary.len() {
+        2 => Language::from_639_1(&primary),
+        3 => Language::from_639_3(&primary),
+        _ => None,
+    }?;
+
+    Some(language.to_639_1().map(String::from).unwrap_or(primary))
+}
+
+// Start of the text, cut at a word
+fn excerpt(text: &str) -> Option<String> {
+    if text.is_empty() {
+        return None
+    }
+    if text.chars().count() <= EXCERPT_LEN {
+        return Some(text.to_string())
+    }
+
+    let cut: String = text.chars().take(EXCERPT_LEN).collect();
+    let cut = cut.rsplitn(2, ' ').last().unwrap_or(&cut);
+    Some(format!("{}…", cut.trim_end_matches(|c: char| c.is_ascii_punctuation())))
+}
+
+// `<meta>` names and contents, with `dcterms.*` read as `dc.*`
+fn metas(soup: &Soup) -> Vec<(String, String)> {
+    soup.tag("meta").find_all()
+        .filter_map(|meta| {
+            let name = meta.get("property").or_else(|| meta.get("name")).or_else(|| meta.get("http-equiv"))?.trim().to_lowercase();
+            let name = match name.strip_prefix("dcterms.") {
+                Some(term) => format!("dc.{}", term),
+                None => name,
+            };
+            let content = meta.get("content")?.trim().to_string();
+            if content.is_empty() { None } else { Some((name, content)) }
+        })
+        .collect()
+}
+
+// Metadata of the first article described by a JSON-LD script
+fn json_ld(soup: &Soup) -> Option<PageMetadata> {
+    soup.tag("script").find_all()
+        .filter(|script| script.get("type").is_some_and(|t| t.trim().to_lowercase().starts_with("application/ld+json")))
+        .filter_map(|script| serde_json::from_str::<Value>(script.text().trim()).ok())
+        .find_map(|value| find_article(&value).map(ld_metadata))
+}
+
+fn find_article(value: &Value) -> Option<&Value> {
+    match value {
+        Value::Array(items) => items.iter().find_map(find_article),
+        Value::Object(object) => {
+            let types = match object.get("@type") {
+                Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+                Some(Value::String(t)) => vec![t.as_str()],
+                _ => Vec::new(),
+            };
+            if types.iter().any(|t| t.ends_with("Article") || t.ends_with("Posting") || *t == "Report") {
+                return Some(value)
+            }
+
+            object.get("@graph").and_then(find_article)
+        },
+        _ => None,
+    }
+}
+
+fn ld_metadata(article: &Value) -> PageMetadata {
+    let text = |key: &str| article.get(key).and_then(Value::as_str).map(str::trim).filter(|t| !t.is_empty()).map(String::from);
+    let authors = article.get("author").map(names).unwrap_or_default();
+
+    PageMetadata {
+        title: text("headline").or_else(|| text("name")),
+        author: if authors.is_empty() { None } else { Some(authors.join(", ")) },
+        date: text("datePublished").or_else(|| text("dateCreated")),
+        language: match article.get("inLanguage") {
+            Some(Value::Object(language)) => language.get("alternateName").and_then(Value::as_str).map(String::from),
+            Some(language) => language.as_str().map(String::from),
+            None => None,
+        },
+        site_name: article.get("publisher").or_else(|| article.get("isPartOf")).map(names).and_then(|names| names.into_iter().next()),
+        url: text("url").or_else(|| match article.get("mainEntityOfPage") {
+            Some(Value::Object(page)) => page.get("@id").and_then(Value::as_str).map(String::from),
+            Some(page) => page.as_str().map(String::from),
+            None => None,
+        }),
+        description: text("description"),
+        keywords: match article.get("keywords") {
+            Some(Value::Array(keywords)) => keywords.iter().filter_map(Value::as_str).map(String::from).collect(),
+            Some(Value::String(keywords)) => split_list(keywords),
+            _ => Vec::new(),
+        },
+    }
+}
+
+// Names of people or organizations, given as text, objects or lists of them
+fn names(value: &Value) -> Vec<String> {
+    let names = match value {
+        Value::String(name) => vec![name.clone()],
+        Value::Object(object) => object.get("name").map(names).unwrap_or_default(),
+        Value::Array(items) => items.iter().flat_map(names).collect(),
+        _ => Vec::new(),
+    };
+
+    names.into_iter()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && !name.starts_with("http"))
+        .collect()
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+// Without repetitions, case aside
+fn dedup(items: Vec<String>) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::new();
+    for item in items {
+        if !unique.iter().any(|u| u.to_lowercase() == item.to_lowercase()) {
+            unique.push(item);
+        }
+    }
+
+    unique
+}
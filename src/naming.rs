@@ -0,0 +1,145 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Longest file stem we produce, in characters
+const MAX_STEM_LEN: usize = 80;
+
+// Used when the template leaves nothing, e.g. an article without title
+const FALLBACK_STEM: &str = "untitled";
+
+/// Values for the placeholders of a file name template.
+#[derive(Default)]
+pub struct NameFields<'a> {
+    pub title: Option<&'a str>,
+    pub author: Option<&'a str>,
+    pub site: Option<&'a str>,  // Host of the article, without `www.`
+    pub date: Option<&'a str>,  // Publication date, today if unknown
+}
+
+/// File stem from `template`, e.g. `{date}-{site}-{title}`. Values are slugified,
+/// unknown placeholders dropped and the result cut to a sensible length.
+pub fn file_stem(template: &str, fields: &NameFields) -> String {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+    let mut stem = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        stem.push_str(&literal(&rest[..start]));
+
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                rest = &rest[start + 1..];  // Unmatched brace, keep what follows as text
+                continue
+            },
+        };
+
+        let value = match &rest[start + 1..end] {
+            "title" => fields.title,
+            "author" => fields.author,
+            "site" => fields.site,
+            "date" => Some(fields.date.unwrap_or(&today)),
+            placeholder => {
+                warn!("Unknown placeholder {{{}}} in file name template", placeholder);
+                None
+            },
+        };
+        stem.push_str(&slugify(value.unwrap_or_default()));
+        rest = &rest[end + 1..];
+    }
+    stem.push_str(&literal(rest));
+
+    // Placeholders left empty leave dangling separators
+    let stem: String = stem.chars().take(MAX_STEM_LEN).collect();
+    let stem = stem.trim_matches(|c: char| c == '-' || c == '_' || c == '.' || c.is_whitespace());
+    if stem.is_empty() { FALLBACK_STEM.into() } else { stem.into() }
+}
+
+/// Lowercase words joined by dashes, e.g. "Hello, World!" becomes "hello-world".
+/// Letters outside ASCII are kept, e-readers display them fine.
+pub fn slugify(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Create an empty `<stem>.<ext>` in `dir`, adding `-2`, `-3`... to the stem if taken,
+/// so that jobs running together never write the same file. Returns its path.
+pub fn reserve(dir: &Path, stem: &str, ext: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    for n in 1.. {
+        let name = match n {
+            1 => format!("{}.{}", stem, ext),
+            n => format!("{}-{}.{}", stem, n, ext),
+        };
+        let path = dir.join(name);
+
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!()
+}
+
+// Text around placeholders, with anything that would change directory removed
+fn literal(text: &str) -> String {
+    text.chars()
+        .filter(|c| !matches!(c, '/' | '\\' | '{' | '}') && !c.is_control())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn reserves_each_name_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("articles");  // Created on demand
+
+        let first = reserve(&out, "news", "epub").unwrap();
+        let second = reserve(&out, "news", "epub").unwrap();
+        assert_eq!(first, out.join("news.epub"));
+        assert_eq!(second, out.join("news-2.epub"));
+        assert_eq!(reserve(&out, "news", "mobi").unwrap(), out.join("news.mobi"));
+
+        // Existing files are never overwritten
+        fs::write(&first, "kept").unwrap();
+        assert_eq!(reserve(&out, "news", "epub").unwrap(), out.join("news-3.epub"));
+        assert_eq!(fs::read_to_string(&first).unwrap(), "kept");
+
+        // Jobs finishing together get names of their own
+        let out = Arc::new(out);
+        let threads: Vec<_> = (0..8).map(|_| {
+            let out = Arc::clone(&out);
+            thread::spawn(move || reserve(&out, "same", "epub").unwrap())
+        }).collect();
+        let mut paths: Vec<PathBuf> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 8);
+    }
+
+    #[test]
+    fn makes_safe_stems() {
+        let fields = NameFields {
+            title: Some("Hello, World! ../../etc"),
+            author: None,
+            site: Some("example.com"),
+            date: Some("2024-01-02"),
+        };
+        assert_eq!(file_stem("{date}-{site}-{title}", &fields), "2024-01-02-example-com-hello-world-etc");
+        assert_eq!(file_stem("../{author}/{title}", &fields), "hello-world-etc");
+        assert_eq!(file_stem("{author}", &fields), FALLBACK_STEM);
+        assert_eq!(file_stem(&"{title}".repeat(20), &fields).chars().count(), MAX_STEM_LEN);
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{self, Read, Write};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use zbus::Connection;
+
+use crate::config::PultConf;
+#[cfg(unix)]
+use crate::dbus::{BUS_NAME, INTERFACE, OBJECT_PATH};
+use crate::download::Task;
+use crate::errors::*;
+use crate::remote;
+use crate::web::Article;
+
+/// Name of the host in the browser manifests, see `data/native-messaging`.
+pub const HOST_NAME: &str = "it.zwitterio.kindle_pult";
+
+// Browsers cap replies at 1 MiB, pages sent to us can be much bigger than that
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Message from the extension: the page, as the browser rendered it if `html` is given.
+#[derive(Deserialize)]
+struct PageMessage {
+    url: String,
+    #[serde(default)]
+    html: Option<String>,
+    #[serde(default)]
+    profile: String,
+    #[serde(default = "default_send")]
+    send: bool,
+}
+
+fn default_send() -> bool {
+    true
+}
+
+#[derive(Default, Serialize)]
+struct Reply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,  // EPUB made from the page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<u64>,  // Job sending it, in the running instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Whether the browser started us as its native messaging host.
+/// Chrome passes the caller's origin, Firefox the path of our manifest and the extension id.
+pub fn is_host_invocation(argv: &[OsString]) -> bool {
+    let manifest = format!("{}.json", HOST_NAME);
+
+    argv.iter().any(|arg| arg.to_str().is_some_and(|arg| arg.starts_with("chrome-extension://")))
+        || argv.first().is_some_and(|arg| Path::new(arg).file_name() == Some(manifest.as_ref()))
+}
+
+/// Answer the extension's messages until it closes the connection.
+/// Messages are JSON, each one preceded by its length as a native-endian `u32`.
+pub fn run() -> io::Result<()> {
+    info!("Started as native messaging host");
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+
+    while let Some(message) = read_message(&mut input)? {
+        let reply = match serde_json::from_slice::<PageMessage>(&message) {
+            Ok(page) => handle(page),
+            Err(e) => Reply {
+                error: Some(format!("Malformed message: {}", e)),
+                ..Reply::default()
+            },
+        };
+        write_message(&mut output, &reply)?;
+    }
+
+    Ok(())
+}
+
+fn handle(page: PageMessage) -> Reply {
+    if !remote::is_web_url(&page.url) {
+        return Reply {
+            error: Some(format!("'{}' is not a web page", page.url)),
+            ..Reply::default()
+        }
+    }
+
+    let cfg = match page.profile.as_str() {
+        "" => PultConf::reload(),
+        profile => PultConf::reload_profile(profile),
+    };
+    let cfg = match cfg {
+        Ok(cfg) => cfg,
+        Err(e) => return Reply {
+            error: Some(e.to_string()),
+            ..Reply::default()
+        },
+    };
+    // Nothing cancels it, the browser waits for the reply
+    let task = Task::default();
+    let epub = match page.html {
+        Some(html) => Article::epub_from_html(page.url.clone(), html, &cfg, &task),
+        None => Article::epub_from_url(page.url.clone(), &cfg, &task),
+    };
+    let path = match epub {
+        Ok(path) => path,
+        Err(e) => {
+            warn!("{}", Report::from(&e).details);
+            return Reply {
+                error: Some(e.to_string()),
+                ..Reply::default()
+            }
+        },
+    };
+
+    let mut reply = Reply {
+        path: Some(path.clone()),
+        ..Reply::default()
+    };
+    if page.send {
+        match queue_file(&path, &page.profile) {
+            Ok(id) => reply.job = Some(id),
+            Err(e) => {
+                warn!("Couldn't queue '{}': {}", path.display(), e);
+                reply.error = Some("Saved, but Kindle-pult isn't running to send it".into());
+            },
+        }
+    }
+
+    reply
+}
+
+// The running instance converts and sends, so the job shows up in its window
+#[cfg(unix)]
+fn queue_file(path: &Path, profile: &str) -> zbus::Result<u64> {
+    let connection = Connection::new_session()?;
+    let reply = connection.call_method(
+        Some(BUS_NAME),
+        OBJECT_PATH,
+        Some(INTERFACE),
+        "QueueFile",
+        &(path.to_string_lossy().as_ref(), profile),
+    )?;
+
+    reply.body::<u64>().map_err(zbus::Error::from)
+}
+
+#[cfg(not(unix))]
+fn queue_file(_path: &Path, _profile: &str) -> Result<u64> {
+    bail!("the running instance is only reachable through D-Bus")
+}
+
+fn read_message<R: Read>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),  // Browser went away
+        Err(e) => return Err(e),
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes is too large", length)))
+    }
+
+    let mut message = vec![0; length];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message<W: Write, T: Serialize>(output: &mut W, message: &T) -> io::Result<()> {
+    let message = serde_json::to_vec(message)?;
+    output.write_all(&(message.len() as u32).to_ne_bytes())?;
+    output.write_all(&message)?;
+    output.flush()
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use glib::ToVariant;
+use gio::prelude::*;
+
+use crate::jobs::{JobEvent, JobId, JobSource, JobState};
+
+// Jobs reported together with a single notification
+struct Batch {
+    ids: Vec<JobId>,
+    finished: HashMap<JobId, JobEvent>,
+}
+
+/// Desktop notifications for finished batches of jobs, whoever submitted them.
+/// Buttons activate the `app.open-folder`, `app.retry` and `app.show-details`
+/// actions, which take a folder path or comma separated job ids.
+pub struct Notifier {
+    app: gtk::Application,
+    batches: Vec<Batch>,
+}
+
+impl Notifier {
+    pub fn new(app: gtk::Application) -> Self {
+        Self {
+            app,
+            batches: Vec::new(),
+        }
+    }
+
+    /// Notify once every job in the batch of `event` has finished.
+    pub fn job_changed(&mut self, event: &JobEvent) {
+        if !event.state.is_finished() {
+            return
+        }
+
+        let i = match self.batches.iter().position(|b| b.ids.contains(&event.id)) {
+            Some(i) => i,
+            None => {
+                self.batches.push(Batch {
+                    ids: event.batch.clone(),
+                    finished: HashMap::new(),
+                });
+                self.batches.len() - 1
+            },
+        };
+
+        let batch = &mut self.batches[i];
+        batch.finished.insert(event.id, event.clone());
+        if batch.finished.len() == batch.ids.len() {
+            let batch = self.batches.remove(i);
+            self.notify(&batch);
+        }
+    }
+
+    fn notify(&self, batch: &Batch) {
+        let events: Vec<&JobEvent> = batch.ids.iter().filter_map(|id| batch.finished.get(id)).collect();
+
+        let failed: Vec<String> = events.iter()
+            .filter(|e| matches!(e.state, JobState::Failed(_)))
+            .map(|e| e.id.to_string())
+            .collect();
+        let succeeded = events.iter()
+            .filter(|e| matches!(e.state, JobState::Done | JobState::Saved))
+            .count();
+
+        if failed.is_empty() && succeeded == 0 {
+            return  // All cancelled by the user, nothing to tell
+        }
+
+        let article = match events.as_slice() {
+            [event] => match &event.source {
+                JobSource::Url(url) => Some((url.clone(), event.state == JobState::Done)),
+                JobSource::File(_) => None,
+            },
+            _ => None,
+        };
+
+        let (title, body) = match (article, failed.is_empty()) {
+            (Some((url, true)), true) => ("Article sent".to_string(), url),
+            (Some((url, false)), true) => ("Article ready".to_string(), url),
+            (Some((url, _)), false) => ("Article download failed".to_string(), url),
+            (None, true) => ("Files sent".to_string(), format!("{} sent to your e-reader", plural(succeeded))),
+            (None, false) => (
+                "Sending finished with errors".to_string(),
+                format!("{} sent, {} failed", plural(succeeded), failed.len()),
+            ),
+        };
+
+        let notification = gio::Notification::new(&title);
+        notification.set_body(Some(&body));
+
+        if failed.is_empty() {
+            let folder = events.iter().find_map(|e| {
+                let file = match (&e.output, &e.source) {
+                    (Some(output), _) => output,
+                    (None, JobSource::File(file)) => file,
+                    (None, JobSource::Url(_)) => return None,
+                };
+                file.parent().map(|dir| dir.to_string_lossy().to_string())
+            });
+
+            if let Some(folder) = folder {
+                notification.add_button_with_target_value("Open folder", "app.open-folder", Some(&folder.to_variant()));
+            }
+        } else {
+            let ids = failed.join(",").to_variant();
+            notification.add_button_with_target_value("Retry", "app.retry", Some(&ids));
+            notification.add_button_with_target_value("Show details", "app.show-details", Some(&ids));
+            notification.set_default_action_and_target_value("app.show-details", Some(&ids));
+        }
+
+        let id = format!("batch-{}", batch.ids[0]);
+        self.app.send_notification(Some(&id), &notification);
+    }
+}
+
+fn plural(files: usize) -> String {
+    if files == 1 { "1 file".into() } else { format!("{} files", files) }
+}
+
+/// Job ids from an action target built by `Notifier`.
+pub fn parse_ids(target: Option<&glib::Variant>) -> Vec<JobId> {
+    target
+        .and_then(|t| t.get_str())
+        .map(|ids| ids.split(',').filter_map(|id| id.parse().ok()).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+
+use html5ever::rcdom::Handle;
+use regex::Regex;
+use soup::prelude::*;
+use url::Url;
+
+use crate::web::Article;
+use crate::xhtml;
+
+// Shorter paragraphs don't count towards their container's score
+const MIN_PARAGRAPH_LEN: usize = 25;
+
+// Patterns from Mozilla's Readability, matched against class and id
+lazy_static! {
+    static ref UNLIKELY: Regex = Regex::new(r"(?i)-ad-|ai2html|banner|breadcrumbs|combx|comment|community|cover-wrap|disqus|extra|footer|gdpr|header|legends|menu|related|remark|replies|rss|shoutbox|sidebar|skyscraper|social|sponsor|supplemental|ad-break|agegate|pagination|pager|popup|yom-remote").unwrap();
+    static ref MAYBE: Regex = Regex::new(r"(?i)and|article|body|column|content|main|shadow").unwrap();
+    static ref POSITIVE: Regex = Regex::new(r"(?i)article|body|content|entry|hentry|h-entry|main|page|pagination|post|text|blog|story").unwrap();
+    static ref NEGATIVE: Regex = Regex::new(r"(?i)-ad-|hidden|^hid$| hid$| hid |^hid |banner|combx|comment|com-|contact|foot|footer|footnote|gdpr|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget").unwrap();
+    static ref BYLINE: Regex = Regex::new(r"(?i)byline|author|dateline|writtenby|p-author").unwrap();
+    static ref TITLE_SEPARATOR: Regex = Regex::new(r" [|\-–—/>»:] ").unwrap();
+}
+
+// Never part of an article
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "iframe", "object", "embed", "form", "input",
+    "button", "select", "textarea", "nav", "aside", "footer", "svg", "canvas", "link", "meta", "head",
+];
+
+// Children making a `div` a container rather than a paragraph
+const BLOCKS: &[&str] = &[
+    "blockquote", "dl", "div", "img", "ol", "p", "pre", "table", "ul", "section", "article", "figure", "header",
+];
+
+// Kept in the extracted content, anything else is replaced by its children
+const ALLOWED: &[&str] = &[
+    "p", "div", "section", "article", "h1", "h2", "h3", "h4", "h5", "h6", "a", "img", "figure", "figcaption",
+    "ul", "ol", "li", "dl", "dt", "dd", "blockquote", "pre", "code", "em", "strong", "b", "i", "u", "s",
+    "sub", "sup", "br", "hr", "table", "thead", "tbody", "tfoot", "tr", "td", "th", "caption", "span",
+    "small", "mark", "abbr", "cite", "q", "time", "del", "ins", "kbd", "var", "samp",
+];
+const ALLOWED_ATTRS: &[&str] = &["href", "src", "alt", "title", "colspan", "rowspan", "datetime"];
+
+// Containers dropped from the content when they look like boilerplate
+const CLEANED: &[&str] = &["div", "section", "ul", "ol", "table", "header"];
+
+/// Find the article in `html`, the way Mozilla's Readability does: paragraphs score
+/// their containers, the best container and its related siblings make the article.
+/// Returns `None` if nothing looks like an article. Short ones are returned too,
+/// `extract::best_article` weighs them against what other engines find.
+pub fn extract(html: &str, url: &Url) -> Option<Article> {
+    let soup = Soup::new(html);
+    let body = soup.tag("body").find().unwrap_or_else(|| soup.get_handle());
+
+    let mut paragraphs = Vec::new();
+    collect_paragraphs(&body, &mut paragraphs);
+
+    // Score the containers of each paragraph, less and less going up
+    let mut scores: HashMap<usize, f64> = HashMap::new();
+    let mut candidates = Vec::new();
+    for paragraph in paragraphs {
+        let text = inner_text(&paragraph);
+        let len = text.trim().chars().count();
+        if len < MIN_PARAGRAPH_LEN {
+            continue
+        }
+
+        let score = 1.0 + text.matches(',').count() as f64 + (len as f64 / 100.0).min(3.0);
+        for (level, ancestor) in paragraph.parents().take(3).enumerate() {
+            if !ancestor.is_element() {
+                break
+            }
+
+            let entry = scores.entry(key(&ancestor)).or_insert_with(|| {
+                candidates.push(ancestor.clone());
+                initial_score(&ancestor)
+            });
+            *entry += score / match level { 0 => 1.0, 1 => 2.0, n => n as f64 * 3.0 };
+        }
+    }
+
+    // Lots of links make a menu, not an article
+    for candidate in &candidates {
+        if let Some(score) = scores.get_mut(&key(candidate)) {
+            *score *= 1.0 - link_density(candidate);
+        }
+    }
+
+    let top = candidates.iter()
+        .max_by(|a, b| scores[&key(a)].partial_cmp(&scores[&key(b)]).unwrap_or(std::cmp::Ordering::Equal))?
+        .clone();
+
+    let mut content = String::from("<div>");
+    for node in with_siblings(&top, &scores) {
+        write_clean(&node, url, true, &mut content);
+    }
+    content.push_str("</div>");
+
+    if Soup::new(&content).text().trim().is_empty() {
+        return None
+    }
+
+    Some(with_metadata(&soup, content))
+}
+
+/// The whole body of `html` as article, only cleaned of scripts, menus and the like.
+/// For pages where no part stands out, e.g. a single long text.
+pub fn whole_page(html: &str, url: &Url) -> Article {
+    let soup = Soup::new(html);
+    let body = soup.tag("body").find().unwrap_or_else(|| soup.get_handle());
+
+    let mut content = String::from("<div>");
+    for child in body.children() {
+        write_clean(&child, url, false, &mut content);
+    }
+    content.push_str("</div>");
+
+    with_metadata(&soup, content)
+}
+
+/// Article made of `nodes` of the document in `soup`, e.g. picked by a site rule.
+pub fn from_nodes(soup: &Soup, nodes: &[Handle], url: &Url) -> Article {
+    let mut content = String::from("<div>");
+    for node in nodes {
+        write_clean(node, url, true, &mut content);
+    }
+    content.push_str("</div>");
+
+    with_metadata(soup, content)
+}
+
+fn with_metadata(soup: &Soup, content: String) -> Article {
+    Article {
+        title: title(soup),
+        byline: byline(soup),
+        date: date(soup),
+        content: Some(content),
+        ..Article::default()
+    }
+}
+
+fn collect_paragraphs(node: &Handle, paragraphs: &mut Vec<Handle>) {
+    for child in node.children().filter(|child| child.is_element()) {
+        let name = child.name().to_string();
+        if SKIPPED.contains(&name.as_str()) || is_hidden(&child) {
+            continue
+        }
+
+        let (class, id) = (child.get("class").unwrap_or_default(), child.get("id").unwrap_or_default());
+        let match_string = format!("{} {}", class, id);
+        if UNLIKELY.is_match(&match_string) && !MAYBE.is_match(&match_string) && !["body", "article", "main"].contains(&name.as_str()) {
+            continue
+        }
+
+        let is_paragraph = match name.as_str() {
+            "p" | "pre" | "td" => true,
+            "div" | "section" | "article" => !child.children().any(|c| c.is_element() && BLOCKS.contains(&c.name())),
+            _ => false,
+        };
+
+        if is_paragraph {
+            paragraphs.push(child);
+        } else {
+            collect_paragraphs(&child, paragraphs);
+        }
+    }
+}
+
+// Top candidate, preceded and followed by siblings that seem to belong to the article
+fn with_siblings(top: &Handle, scores: &HashMap<usize, f64>) -> Vec<Handle> {
+    let parent = match top.parent() {
+        Some(parent) if parent.is_element() && top.name() != "body" => parent,
+        _ => return vec![top.clone()],
+    };
+
+    let top_score = scores[&key(top)];
+    let threshold = (top_score * 0.2).max(10.0);
+    let top_class = top.get("class").unwrap_or_default();
+
+    parent.children()
+        .filter(|sibling| sibling.is_element())
+        .filter(|sibling| {
+            if key(sibling) == key(top) {
+                return true
+            }
+
+            let mut score = scores.get(&key(sibling)).copied().unwrap_or(0.0);
+            if !top_class.is_empty() && sibling.get("class").unwrap_or_default() == top_class {
+                score += top_score * 0.2;
+            }
+            if score >= threshold {
+                return true
+            }
+
+            if sibling.name() != "p" {
+                return false
+            }
+            let text = inner_text(sibling);
+            let len = text.trim().chars().count();
+            let density = link_density(sibling);
+            (len > 80 && density < 0.25) || (len > 0 && density == 0.0 && text.contains(". "))
+        })
+        .collect()
+}
+
+// Serialize the article, leaving out scripts, hidden elements and boilerplate
+fn write_clean(node: &Handle, url: &Url, top: bool, out: &mut String) {
+    if node.is_text() {
+        out.push_str(&xhtml::escape(&node.text()));
+        return
+    }
+    if !node.is_element() {
+        return
+    }
+
+    let name = node.name().to_string();
+    if SKIPPED.contains(&name.as_str()) || is_hidden(node) || (!top && is_boilerplate(node)) {
+        return
+    }
+
+    if !ALLOWED.contains(&name.as_str()) {
+        for child in node.children() {
+            write_clean(&child, url, false, out);
+        }
+        return
+    }
+
+    out.push('<');
+    out.push_str(&name);
+    for (attr, value) in node.attrs() {
+        if !ALLOWED_ATTRS.contains(&attr.as_str()) {
+            continue
+        }
+
+        // Links are followed from the e-reader, they must be absolute
+        let value = match attr.as_str() {
+            "href" if value.trim_start().to_lowercase().starts_with("javascript:") => continue,
+            "href" => url.join(&value).map(String::from).unwrap_or(value),
+            _ => value,
+        };
+        out.push_str(&format!(" {}=\"{}\"", attr, xhtml::escape_attr(&value)));
+    }
+
+    // Closed the XML way, the content ends up in XHTML
+    if xhtml::VOID.contains(&name.as_str()) {
+        out.push_str("/>");
+        return
+    }
+    out.push('>');
+
+    for child in node.children() {
+        write_clean(&child, url, false, out);
+    }
+    out.push_str(&format!("</{}>", name));
+}
+
+// Containers inside the article that are likely share buttons, related links and the like
+fn is_boilerplate(node: &Handle) -> bool {
+    // Already in the metadata
+    let match_string = format!("{} {}", node.get("class").unwrap_or_default(), node.get("id").unwrap_or_default());
+    if BYLINE.is_match(&match_string) && inner_text(node).trim().chars().count() < 100 {
+        return true
+    }
+
+    if !CLEANED.contains(&node.name()) {
+        return false
+    }
+
+    let weight = class_weight(node);
+    let text_len = inner_text(node).trim().chars().count();
+    let has_images = node.tag("img").find().is_some();
+
+    (weight < 0.0 && text_len < 200)
+        || (weight < 25.0 && link_density(node) > 0.5)
+        || (text_len == 0 && !has_images)
+}
+
+fn initial_score(node: &Handle) -> f64 {
+    let tag_score = match node.name() {
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+
+    tag_score + class_weight(node)
+}
+
+fn class_weight(node: &Handle) -> f64 {
+    let mut weight = 0.0;
+    for attr in &["class", "id"] {
+        if let Some(value) = node.get(attr).filter(|v| !v.is_empty()) {
+            if NEGATIVE.is_match(&value) {
+                weight -= 25.0;
+            }
+            if POSITIVE.is_match(&value) {
+                weight += 25.0;
+            }
+        }
+    }
+
+    weight
+}
+
+// Share of the text that is in links
+fn link_density(node: &Handle) -> f64 {
+    let len = inner_text(node).trim().chars().count();
+    if len == 0 {
+        return 0.0
+    }
+
+    let link_len: usize = node.tag("a").find_all()
+        .map(|a| inner_text(&a).trim().chars().count())
+        .sum();
+    link_len as f64 / len as f64
+}
+
+fn is_hidden(node: &Handle) -> bool {
+    let style = node.get("style").unwrap_or_default().replace(' ', "").to_lowercase();
+
+    node.get("hidden").is_some()
+        || node.get("aria-hidden").as_deref() == Some("true")
+        || style.contains("display:none")
+        || style.contains("visibility:hidden")
+}
+
+// Visible text, unlike `NodeExt::text` which includes scripts and styles
+fn inner_text(node: &Handle) -> String {
+    let mut text = String::new();
+    for child in node.children() {
+        if child.is_text() {
+            text.push_str(&child.text());
+        } else if child.is_element() && !SKIPPED.contains(&child.name()) {
+            text.push_str(&inner_text(&child));
+        }
+    }
+
+    text
+}
+
+// Scores are kept by node identity
+fn key(node: &Handle) -> usize {
+    &**node as *const _ as usize
+}
+
+// Content of the first `<meta>` found of `names`, in their order of preference
+fn meta(soup: &Soup, names: &[&str]) -> Option<String> {
+    let metas: Vec<Handle> = soup.tag("meta").find_all().collect();
+    names.iter().find_map(|name| {
+        metas.iter()
+            .filter(|meta| meta.get("property").or_else(|| meta.get("name")).is_some_and(|n| n.eq_ignore_ascii_case(name)))
+            .filter_map(|meta| meta.get("content"))
+            .map(|content| content.trim().to_string())
+            .find(|content| !content.is_empty())
+    })
+}
+
+fn title(soup: &Soup) -> Option<String> {
+    if let Some(title) = meta(soup, &["og:title", "twitter:title", "dc.title"]) {
+        return Some(title)
+    }
+
+    // `<title>` usually carries the site name too, e.g. "Article - Site"
+    if let Some(title) = soup.tag("title").find().map(|t| t.text().trim().to_string()).filter(|t| !t.is_empty()) {
+        let head = TITLE_SEPARATOR.split(&title).next().unwrap_or_default().trim();
+        return Some(if head.split_whitespace().count() >= 3 { head.into() } else { title })
+    }
+
+    soup.tag("h1").find().map(|h1| inner_text(&h1).trim().to_string()).filter(|t| !t.is_empty())
+}
+
+fn byline(soup: &Soup) -> Option<String> {
+    let author = meta(soup, &["author", "article:author", "dc.creator", "twitter:creator"])
+        .filter(|author| !author.starts_with("http"));
+    if author.is_some() {
+        return author
+    }
+
+    soup.get_handle().tag(true).find_all()
+        .filter(|node| {
+            let match_string = format!("{} {}", node.get("class").unwrap_or_default(), node.get("id").unwrap_or_default());
+            node.get("rel").as_deref() == Some("author") || node.get("itemprop").is_some_and(|p| p.contains("author")) || BYLINE.is_match(&match_string)
+        })
+        .map(|node| inner_text(&node).split_whitespace().collect::<Vec<_>>().join(" "))
+        .find(|text| !text.is_empty() && text.chars().count() < 100)
+}
+
+fn date(soup: &Soup) -> Option<String> {
+    meta(soup, &["article:published_time", "date", "pubdate", "dc.date", "dc.date.issued", "publish-date"])
+        .or_else(|| soup.tag("time").find().and_then(|time| time.get("datetime")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    // A saved page and what its article should be made of
+    struct Case {
+        file: &'static str,
+        url: &'static str,
+        title: &'static str,
+        byline: &'static str,
+        kept: &'static [&'static str],  // Text from the article
+        dropped: &'static [&'static str],  // Text from menus, comments and the like
+    }
+
+    const CASES: &[Case] = &[
+        Case {
+            file: "news.html",
+            url: "https://news.example.com/2021/03/bike-lanes",
+            title: "City council approves new bike lanes across downtown",
+            byline: "Maria Rossi",
+            kept: &["twelve kilometres of protected bike lanes", "completed by the end of next year", "April\u{a0}2", "4.2 million euros"],
+            dropped: &["Most read", "Subscribe to our newsletter", "We use cookies", "Finally! I have been waiting", "Share on Twitter"],
+        },
+        Case {
+            file: "blog.html",
+            url: "https://attic.example.org/2020/11/paper-notebooks",
+            title: "Why I switched back to paper notebooks",
+            byline: "Jane Doe",
+            kept: &["For almost ten years", "remember with it", "six months in"],
+            dropped: &["Related posts", "3 responses", "Tracking pixel", "Powered by"],
+        },
+        Case {
+            file: "tutorial.html",
+            url: "https://systems.example.net/rust/lifetimes",
+            title: "Q&A: Lifetimes <'a> without tears",
+            byline: "Luca Bianchi",
+            kept: &["fn longest<'a>(x: &'a str", "Should I just clone everything?", "Two reference arguments"],
+            dropped: &["uses cookies", "newsletter, free every Friday", "all rights reserved", "Tweet"],
+        },
+    ];
+
+    fn fixture(file: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(file);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+    }
+
+    #[test]
+    fn extracts_saved_pages() {
+        for case in CASES {
+            let url = Url::parse(case.url).unwrap();
+            let article = extract(&fixture(case.file), &url)
+                .unwrap_or_else(|| panic!("{}: no article found", case.file));
+
+            assert_eq!(article.title.as_deref(), Some(case.title), "{}", case.file);
+            assert_eq!(article.byline.as_deref(), Some(case.byline), "{}", case.file);
+
+            let content = article.content.unwrap();
+            let text = Soup::new(&content).text();
+            assert!(text.trim().chars().count() >= 500, "{}: article too short", case.file);
+            for kept in case.kept {
+                assert!(text.contains(kept), "{}: '{}' missing", case.file, kept);
+            }
+            for dropped in case.dropped {
+                assert!(!text.contains(dropped), "{}: '{}' left in", case.file, dropped);
+            }
+        }
+    }
+
+    #[test]
+    fn writes_well_formed_xml() {
+        for case in CASES {
+            let url = Url::parse(case.url).unwrap();
+            let content = extract(&fixture(case.file), &url).and_then(|article| article.content).unwrap();
+
+            if let Err(e) = roxmltree::Document::parse(&content) {
+                panic!("{}: {}\n{}", case.file, e, content);
+            }
+        }
+    }
+}
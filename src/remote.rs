@@ -0,0 +1,76 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: kindle-pult [OPTIONS] [FILE|URL]...
+
+Files are added to the selection, web pages are downloaded as articles.
+If Kindle-pult is already running, everything goes to its window.
+
+Options:
+  --send FILE       Convert and send FILE right away
+  --url URL         Download URL as an article
+  --profile NAME    Use the NAME profile for --send and --url
+  -h, --help        Print this help";
+
+/// Something to queue, from the command line or other applications.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    Open(PathBuf),  // Add to the selection, sent with the Send button
+    Send(PathBuf),  // Convert and send right away
+    Url(String),  // Download as an article
+}
+
+/// Requests of a single invocation, sharing one profile.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Invocation {
+    pub profile: Option<String>,
+    pub requests: Vec<Request>,
+}
+
+/// Command line argument, before resolving files against the caller's directory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    Positional(OsString),  // File or URL
+    Send(OsString),
+    Url(String),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub help: bool,
+    pub profile: Option<String>,
+    pub args: Vec<Arg>,
+}
+
+/// Parse arguments, program name excluded.
+pub fn parse_args(argv: &[OsString]) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut argv = argv.iter();
+
+    while let Some(arg) = argv.next() {
+        match arg.to_str() {
+            Some("-h") | Some("--help") => parsed.help = true,
+            Some("--send") => {
+                let file = argv.next().ok_or("--send needs a FILE")?;
+                parsed.args.push(Arg::Send(file.clone()));
+            },
+            Some("--url") => {
+                let url = argv.next().and_then(|u| u.to_str()).ok_or("--url needs a URL")?;
+                parsed.args.push(Arg::Url(url.into()));
+            },
+            Some("--profile") => {
+                let name = argv.next().and_then(|p| p.to_str()).ok_or("--profile needs a NAME")?;
+                parsed.profile = Some(name.into());
+            },
+            Some(option) if option.starts_with("--") => return Err(format!("Unknown option {}", option)),
+            _ => parsed.args.push(Arg::Positional(arg.clone())),
+        }
+    }
+
+    Ok(parsed)
+}
+
+pub fn is_web_url(arg: &str) -> bool {
+    arg.starts_with("http://") || arg.starts_with("https://")
+}
@@ -0,0 +1,292 @@
+use std::fs;
+use std::rc::Rc;
+
+use html5ever::rcdom::Handle;
+use serde::Deserialize;
+use soup::prelude::*;
+use url::Url;
+
+use crate::config;
+use crate::errors::*;
+use crate::web::Article;
+
+// Rules shipped with Kindle-pult, overridden by the user's ones
+const BUNDLED_RULES: &str = include_str!("../data/site-rules.toml");
+
+// Domain of rules applying to every site, e.g. to remove cookie banners
+const ANY_SITE: &str = "*";
+
+/// How to read the articles of a site. Selectors are CSS-like: `tag`, `#id`, `.class`,
+/// `[attr]` and `[attr=value]`, combined (`div.post`), nested (`article .body`) or
+/// as alternatives (`.byline, .author`).
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SiteRule {
+    pub domains: Vec<String>,  // Subdomains included
+    pub content: Option<String>,  // The article, skipping generic extraction unless nothing matches
+    pub remove: Vec<String>,  // Banners, sign-up forms, related articles...
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+    pub next_page: Option<String>,  // Link to the rest of the article, for paginated ones
+}
+
+#[derive(Default, Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    site: Vec<SiteRule>,
+}
+
+/// The rule for `url`, from `site-rules.toml` (or `.json`) in the config directory
+/// or else from the bundled rules. Removals of rules for every site (`*`) are added.
+pub fn rule_for(url: &Url) -> SiteRule {
+    let host = url.host_str().unwrap_or_default();
+    let user_rules = user_rules();
+    let bundled_rules = parse_rules(BUNDLED_RULES, "toml").unwrap_or_default();
+
+    // A user rule for a domain replaces the bundled one
+    let find = |applies: &dyn Fn(&SiteRule) -> bool| {
+        user_rules.iter().find(|rule| applies(rule))
+            .or_else(|| bundled_rules.iter().find(|rule| applies(rule)))
+            .cloned()
+    };
+
+    let mut rule = find(&|rule| rule.domains.iter().any(|domain| matches_domain(host, domain)))
+        .unwrap_or_default();
+    if let Some(any_site) = find(&|rule| rule.domains.iter().any(|domain| domain == ANY_SITE)) {
+        rule.remove.extend(any_site.remove);
+    }
+
+    rule
+}
+
+fn user_rules() -> Vec<SiteRule> {
+    let dir = match config::config_dir() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+
+    for format in &["toml", "json"] {
+        let path = dir.join(format!("site-rules.{}", format));
+        if !path.is_file() {
+            continue
+        }
+
+        match fs::read_to_string(&path).map_err(Error::from).and_then(|text| parse_rules(&text, format)) {
+            Ok(rules) => return rules,
+            Err(e) => warn!("Ignoring {}: {}", path.display(), e),
+        }
+    }
+
+    Vec::new()
+}
+
+fn parse_rules(text: &str, format: &str) -> Result<Vec<SiteRule>> {
+    let file: RulesFile = match format {
+        "json" => serde_json::from_str(text)?,
+        _ => toml::from_str(text).map_err(|e| Error::from(e.to_string()))?,
+    };
+
+    Ok(file.site)
+}
+
+fn matches_domain(host: &str, domain: &str) -> bool {
+    domain != ANY_SITE && !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)))
+}
+
+impl SiteRule {
+    /// `html` without the elements of `remove`.
+    pub fn clean(&self, html: &str) -> String {
+        if self.remove.is_empty() {
+            return html.to_string()
+        }
+
+        let soup = Soup::new(html);
+        let document = soup.get_handle();
+        for selector in &self.remove {
+            for node in select(&document, selector) {
+                detach(&node);
+            }
+        }
+
+        let mut cleaned = Vec::new();
+        match html5ever::serialize(&mut cleaned, &document, Default::default()) {
+            Ok(()) => String::from_utf8_lossy(&cleaned).into_owned(),
+            Err(e) => {
+                warn!("Couldn't apply site rules: {}", e);
+                html.to_string()
+            },
+        }
+    }
+
+    /// Replace the metadata of `article` with what the rule's selectors find in `html`.
+    pub fn fix_metadata(&self, article: &mut Article, html: &str) {
+        let soup = Soup::new(html);
+        let document = soup.get_handle();
+        let first_text = |selector: &Option<String>, attrs: &[&str]| {
+            let node = selector.as_ref().and_then(|selector| select(&document, selector).into_iter().next())?;
+            let text = attrs.iter().find_map(|attr| node.get(attr))
+                .unwrap_or_else(|| node.text())
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if text.is_empty() { None } else { Some(text) }
+        };
+
+        if let Some(title) = first_text(&self.title, &[]) {
+            article.title = Some(title);
+        }
+        if let Some(author) = first_text(&self.author, &[]) {
+            article.byline = Some(author);
+        }
+        if let Some(date) = first_text(&self.date, &["datetime", "content"]) {
+            article.date = Some(date);
+        }
+    }
+
+    /// Address of the next page of the article, if `html` links to one.
+    pub fn next_page(&self, html: &str, url: &Url) -> Option<Url> {
+        let soup = Soup::new(html);
+        let selector = self.next_page.as_ref()?;
+        let link = select(&soup.get_handle(), selector).into_iter().next()?;
+
+        url.join(&link.get("href")?).ok().filter(|next| next != url)
+    }
+}
+
+/// Elements under `root` matching `selector`, in document order for each alternative.
+pub fn select(root: &Handle, selector: &str) -> Vec<Handle> {
+    let mut found: Vec<Handle> = Vec::new();
+
+    for alternative in selector.split(',') {
+        let chain: Vec<Compound> = alternative.split_whitespace().map(Compound::parse).collect();
+        if chain.is_empty() {
+            continue
+        }
+
+        let mut scopes = vec![root.clone()];
+        for compound in &chain {
+            let mut matched: Vec<Handle> = Vec::new();
+            for scope in &scopes {
+                for node in descendants(scope) {
+                    if compound.matches(&node) && !matched.iter().any(|m| Rc::ptr_eq(m, &node)) {
+                        matched.push(node);
+                    }
+                }
+            }
+            scopes = matched;
+        }
+
+        for node in scopes {
+            if !found.iter().any(|f| Rc::ptr_eq(f, &node)) {
+                found.push(node);
+            }
+        }
+    }
+
+    found
+}
+
+// Single element condition, like `div.post[data-id=3]`
+#[derive(Default)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attrs: Vec<(String, Option<String>)>,
+    invalid: bool,  // Unsupported syntax, matches nothing
+}
+
+impl Compound {
+    fn parse(text: &str) -> Self {
+        let mut compound = Compound::default();
+        let mut rest = text;
+
+        let tag_end = rest.find(['.', '#', '[']).unwrap_or(rest.len());
+        match &rest[..tag_end] {
+            "" | "*" => (),
+            tag if tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') => compound.tag = Some(tag.to_lowercase()),
+            _ => compound.invalid = true,
+        }
+        rest = &rest[tag_end..];
+
+        while !rest.is_empty() && !compound.invalid {
+            let (kind, body) = rest.split_at(1);
+            let end = match kind {
+                "[" => body.find(']').map(|end| end + 1),
+                _ => Some(body.find(['.', '#', '[']).unwrap_or(body.len())),
+            };
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    compound.invalid = true;
+                    break
+                },
+            };
+
+            let part = &body[..end];
+            match kind {
+                "." => compound.classes.push(part.to_string()),
+                "#" => compound.id = Some(part.to_string()),
+                _ => {
+                    let attr = part.trim_end_matches(']');
+                    let mut pair = attr.splitn(2, '=');
+                    let name = pair.next().unwrap_or_default().trim().to_lowercase();
+                    let value = pair.next().map(|v| v.trim().trim_matches(|c| c == '"' || c == '\'').to_string());
+                    compound.attrs.push((name, value));
+                },
+            }
+            rest = &body[end..];
+        }
+
+        if compound.invalid {
+            warn!("Unsupported selector '{}'", text);
+        }
+        compound
+    }
+
+    fn matches(&self, node: &Handle) -> bool {
+        if self.invalid || !node.is_element() {
+            return false
+        }
+
+        if let Some(tag) = &self.tag {
+            if node.name() != tag {
+                return false
+            }
+        }
+        if let Some(id) = &self.id {
+            if node.get("id").as_ref() != Some(id) {
+                return false
+            }
+        }
+
+        let class = node.get("class").unwrap_or_default();
+        if !self.classes.iter().all(|wanted| class.split_whitespace().any(|c| c == wanted)) {
+            return false
+        }
+
+        self.attrs.iter().all(|(name, value)| match (node.get(name), value) {
+            (Some(actual), Some(value)) => &actual == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+    }
+}
+
+fn descendants(node: &Handle) -> Vec<Handle> {
+    let mut all = Vec::new();
+    for child in node.children() {
+        all.push(child.clone());
+        all.extend(descendants(&child));
+    }
+
+    all
+}
+
+/// Remove `node` from its document.
+pub fn detach(node: &Handle) {
+    if let Some(parent) = node.parent() {
+        parent.children.borrow_mut().retain(|child| !Rc::ptr_eq(child, node));
+    }
+}
@@ -0,0 +1,336 @@
+use std::io::{self, Read};
+use std::fs;
+use std::env;
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::config::PultConf;
+use crate::jobs::{JobEvent, JobId, JobPool, JobSource, JobState};
+use crate::remote;
+
+const MAX_UPLOAD_SIZE: usize = 100 * 1024 * 1024;
+const MAX_JSON_SIZE: usize = 64 * 1024;
+
+const INDEX_HTML: &str = include_str!("../data/index.html");
+
+#[derive(Deserialize)]
+struct UrlRequest {
+    url: String,
+    #[serde(default)]
+    profile: String,
+}
+
+#[derive(Serialize)]
+struct JobStatus {
+    id: JobId,
+    source: String,
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    done: Option<usize>,  // Files of the current state, e.g. article images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl From<&JobEvent> for JobStatus {
+    fn from(event: &JobEvent) -> Self {
+        Self {
+            id: event.id,
+            source: event.source.name(),
+            state: event.state.name(),
+            done: event.progress.map(|(done, _)| done),
+            total: event.progress.map(|(_, total)| total),
+            error: match &event.state {
+                JobState::Failed(report) => Some(report.summary.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Small HTTP front end to the job queue, for phones and bookmarklets:
+///
+/// - `GET /`: upload form;
+/// - `POST /api/url`: `{"url": ..., "profile": ...}`, download a page as an article;
+/// - `POST /api/file?name=<file name>&profile=<name>`: file in the body, convert and send it;
+/// - `GET /api/jobs` and `GET /api/jobs/<id>`: job states.
+///
+/// API calls need the configured token, as `Authorization: Bearer <token>` or `?token=<token>`.
+struct WebQueue {
+    jobs: JobPool,
+    cfg: HashMap<String, String>,
+    token: String,
+}
+
+/// Start serving, if enabled in `cfg`, on a thread of its own.
+pub fn spawn(jobs: JobPool, cfg: HashMap<String, String>) {
+    if !cfg.get("http_enabled").unwrap().parse().unwrap_or(false) {
+        return
+    }
+
+    let token = cfg.get("http_token").unwrap().clone();
+    if token.len() < 16 {
+        warn!("Web access disabled: set an access token of at least 16 characters");
+        return
+    }
+
+    let address = cfg.get("http_address").unwrap().clone();
+    let server = match Server::http(address.as_str()) {
+        Ok(server) => server,
+        Err(e) => {
+            warn!("Couldn't listen on {}: {}", address, e);
+            return
+        },
+    };
+    info!("Web access on http://{}/", address);
+
+    let queue = WebQueue { jobs, cfg, token };
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            queue.handle(request);
+        }
+    });
+}
+
+impl WebQueue {
+    fn handle(&self, mut request: Request) {
+        // Relative URLs need a base
+        let url = match Url::parse("http://localhost").and_then(|base| base.join(request.url())) {
+            Ok(url) => url,
+            Err(_) => return respond(request, error(400, "malformed URL")),
+        };
+        let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+
+        if (request.method(), url.path()) == (&Method::Get, "/") {
+            let html = Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).unwrap();
+            return respond(request, Response::from_string(INDEX_HTML).with_header(html))
+        }
+
+        if !url.path().starts_with("/api/") {
+            return respond(request, error(404, "not found"))
+        }
+
+        let bearer = request.headers().iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .and_then(|h| h.value.as_str().strip_prefix("Bearer ").map(String::from));
+        let token = bearer.or_else(|| param("token")).unwrap_or_default();
+        if !same_secret(&token, &self.token) {
+            warn!("Web access: rejected request from {}", request.remote_addr());
+            return respond(request, error(401, "missing or wrong token"))
+        }
+
+        let response = match (request.method().clone(), url.path()) {
+            (Method::Post, "/api/url") => self.queue_url(&mut request),
+            (Method::Post, "/api/file") => self.queue_file(&mut request, param("name"), param("profile")),
+            (Method::Get, "/api/jobs") => {
+                let jobs: Vec<JobStatus> = self.jobs.list().iter().map(JobStatus::from).collect();
+                json(200, &jobs)
+            },
+            (Method::Get, path) if path.starts_with("/api/jobs/") => {
+                let id = path.trim_start_matches("/api/jobs/").parse::<JobId>().ok();
+                match id.and_then(|id| self.jobs.list().into_iter().find(|job| job.id == id)) {
+                    Some(job) => json(200, &JobStatus::from(&job)),
+                    None => error(404, "no such job"),
+                }
+            },
+            _ => error(404, "not found"),
+        };
+
+        respond(request, response)
+    }
+
+    fn queue_url(&self, request: &mut Request) -> Response<io::Cursor<Vec<u8>>> {
+        let mut body = Vec::new();
+        if request.as_reader().take(MAX_JSON_SIZE as u64).read_to_end(&mut body).is_err() {
+            return error(400, "couldn't read request")
+        }
+
+        let url_request: UrlRequest = match serde_json::from_slice(&body) {
+            Ok(url_request) => url_request,
+            Err(_) => return error(400, "expected {\"url\": ...}"),
+        };
+        if !remote::is_web_url(&url_request.url) || Url::parse(&url_request.url).is_err() {
+            return error(400, "not a web page")
+        }
+
+        match self.cfg_for(&url_request.profile) {
+            Ok(cfg) => self.accepted(JobSource::Url(url_request.url), cfg),
+            Err(response) => response,
+        }
+    }
+
+    fn queue_file(&self, request: &mut Request, name: Option<String>, profile: Option<String>) -> Response<io::Cursor<Vec<u8>>> {
+        // Keep just the file name, the client doesn't choose where it goes
+        let name = name.as_ref()
+            .and_then(|name| Path::new(name).file_name())
+            .map(PathBuf::from);
+        let name = match name {
+            Some(name) if name.extension().is_some() => name,
+            _ => return error(400, "a file name with extension is needed"),
+        };
+
+        if request.body_length().is_some_and(|len| len > MAX_UPLOAD_SIZE) {
+            return error(413, "file too large")
+        }
+
+        let cfg = match self.cfg_for(&profile.unwrap_or_default()) {
+            Ok(cfg) => cfg,
+            Err(response) => return response,
+        };
+
+        let mut body = Vec::new();
+        if request.as_reader().take(MAX_UPLOAD_SIZE as u64 + 1).read_to_end(&mut body).is_err() {
+            return error(400, "couldn't read request")
+        }
+        if body.len() > MAX_UPLOAD_SIZE {
+            return error(413, "file too large")
+        }
+
+        match save_upload(&name, &body) {
+            Ok((dir, file)) => {
+                let id = self.jobs.submit_in(JobSource::File(file), cfg, dir);
+                self.status(id)
+            },
+            Err(e) => {
+                warn!("Couldn't save upload: {}", e);
+                error(500, "couldn't save the file")
+            },
+        }
+    }
+
+    fn cfg_for(&self, profile: &str) -> Result<HashMap<String, String>, Response<io::Cursor<Vec<u8>>>> {
+        if profile.is_empty() {
+            return Ok(self.cfg.clone())
+        }
+
+        PultConf::reload_profile(profile).map_err(|e| error(400, &e.to_string()))
+    }
+
+    fn accepted(&self, source: JobSource, cfg: HashMap<String, String>) -> Response<io::Cursor<Vec<u8>>> {
+        let id = self.jobs.submit(source, cfg);
+        self.status(id)
+    }
+
+    fn status(&self, id: JobId) -> Response<io::Cursor<Vec<u8>>> {
+        match self.jobs.list().iter().find(|job| job.id == id) {
+            Some(job) => json(202, &JobStatus::from(job)),
+            None => json(202, &serde_json::json!({ "id": id })),
+        }
+    }
+}
+
+// Each upload gets a directory of its own, so names never clash.
+// It lives as long as the job, the converted file goes there too unless an output folder is set.
+fn save_upload(name: &Path, body: &[u8]) -> io::Result<(TempDir, PathBuf)> {
+    let dir = tempfile::Builder::new()
+        .prefix("upload_")
+        .tempdir_in(uploads_dir()?)?;
+
+    let file = dir.path().join(name);
+    fs::write(&file, body)?;
+    Ok((dir, file))
+}
+
+// Per user, not in the shared temporary folder where others could create it first
+fn uploads_dir() -> io::Result<PathBuf> {
+    let base = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| directories::ProjectDirs::from("rs", "", "kindle-pult").map(|dirs| dirs.cache_dir().to_path_buf()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no folder for uploads"))?;
+
+    let dir = base.join("kindle-pult-uploads");
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+// `dir` readable by this user only, refused if it's a link or someone else's
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        result => result?,
+    }
+
+    let metadata = fs::symlink_metadata(dir)?;
+    let owner = unsafe { libc::geteuid() };
+    if !metadata.is_dir() || metadata.uid() != owner || metadata.permissions().mode() & 0o077 != 0 {
+        let message = format!("'{}' isn't a private folder of this user", dir.display());
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, message))
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+// Compare without returning early, so timing doesn't leak the token
+fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn json<T: Serialize>(status: u16, body: &T) -> Response<io::Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    Response::from_string(serde_json::to_string(body).unwrap_or_default())
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn error(status: u16, message: &str) -> Response<io::Cursor<Vec<u8>>> {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+fn respond<R: Read>(request: Request, response: Response<R>) {
+    if let Err(e) = request.respond(response) {
+        debug!("Couldn't answer HTTP request: {}", e);
+    }
+}
+
+/// Random access token for the web API.
+pub fn new_token() -> String {
+    use rand::Rng;
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn keeps_uploads_private() {
+        let base = tempfile::tempdir().unwrap();
+
+        let dir = base.path().join("uploads");
+        create_private_dir(&dir).unwrap();
+        create_private_dir(&dir).unwrap();  // Again, as at the next start
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+
+        let shared = base.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(create_private_dir(&shared).is_err());
+
+        let link = base.path().join("link");
+        symlink(&dir, &link).unwrap();
+        assert!(create_private_dir(&link).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::path::PathBuf;
+use url::Url;
+
+use crate::errors::*;
+use crate::remote::{self, Invocation, Request};
+
+pub const SCHEME: &str = "kindle-pult";
+const ORG_PROTOCOL: &str = "org-protocol";
+
+/// Whether `arg` is one of our links, to be handled by `parse`.
+pub fn is_pult_uri(arg: &str) -> bool {
+    arg.starts_with("kindle-pult:") || arg.starts_with("org-protocol://kindle-pult") || arg.starts_with("org-protocol:/kindle-pult")
+}
+
+/// Turn a link into requests. Supported forms:
+///
+/// - `kindle-pult://send?url=<page>&profile=<name>`: download the page as an article;
+/// - `kindle-pult://open?file=<absolute path>`: add a local file to the selection;
+/// - `org-protocol://kindle-pult?url=<page>` and the legacy `org-protocol:/kindle-pult:/<encoded page>`.
+///
+/// Links can come from any web page, so they can't send local files directly:
+/// files only get selected and still wait for the Send button.
+pub fn parse(uri: &str) -> Result<Invocation> {
+    let invalid = |reason: &str| Error::from(ErrorKind::InvalidRequest(format!("{}: {}", reason, uri)));
+
+    let parsed = Url::parse(uri).map_err(|_| invalid("malformed link"))?;
+    let param = |name: &str| parsed.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+
+    let action = match parsed.scheme() {
+        SCHEME => parsed.host_str().unwrap_or_default().to_string(),
+        ORG_PROTOCOL => {
+            if let Some(page) = legacy_org_protocol_url(uri) {
+                return article(page, None).map_err(|_| invalid("not a web page"))
+            }
+            match parsed.host_str() {
+                Some(SCHEME) => "send".to_string(),
+                _ => return Err(invalid("not a kindle-pult link")),
+            }
+        },
+        _ => return Err(invalid("not a kindle-pult link")),
+    };
+
+    match action.as_str() {
+        "send" => {
+            let page = param("url").ok_or_else(|| invalid("missing url"))?;
+            article(page, param("profile")).map_err(|_| invalid("not a web page"))
+        },
+        "open" => {
+            let file = PathBuf::from(param("file").ok_or_else(|| invalid("missing file"))?);
+            if !file.is_absolute() {
+                return Err(invalid("file path must be absolute"))
+            }
+
+            Ok(Invocation {
+                profile: None,
+                requests: vec![Request::Open(file)],
+            })
+        },
+        _ => Err(invalid("unknown action")),
+    }
+}
+
+fn article(page: String, profile: Option<String>) -> std::result::Result<Invocation, ()> {
+    if !remote::is_web_url(&page) || Url::parse(&page).is_err() {
+        return Err(())
+    }
+
+    Ok(Invocation {
+        profile: profile.filter(|p| !p.is_empty()),
+        requests: vec![Request::Url(page)],
+    })
+}
+
+// `org-protocol:/kindle-pult:/https%3A%2F%2F...`, the page being the rest of the link
+fn legacy_org_protocol_url(uri: &str) -> Option<String> {
+    let rest = uri.strip_prefix("org-protocol:")?.trim_start_matches('/');
+    let encoded = rest.strip_prefix("kindle-pult:")?.trim_start_matches('/');
+    if encoded.is_empty() {
+        return None
+    }
+
+    url::form_urlencoded::parse(format!("u={}", encoded).as_bytes())
+        .next()
+        .map(|(_, page)| page.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn article_of(page: &str, profile: Option<&str>) -> Invocation {
+        Invocation {
+            profile: profile.map(String::from),
+            requests: vec![Request::Url(page.into())],
+        }
+    }
+
+    #[test]
+    fn parses_links() {
+        let page = "https://example.com/a?b=1&c=2";
+        let cases = [
+            ("kindle-pult://send?url=https%3A%2F%2Fexample.com%2Fa%3Fb%3D1%26c%3D2", article_of(page, None)),
+            ("kindle-pult://send?url=https%3A%2F%2Fexample.com%2Fa%3Fb%3D1%26c%3D2&profile=work", article_of(page, Some("work"))),
+            ("kindle-pult://send?profile=&url=https%3A%2F%2Fexample.com%2Fa%3Fb%3D1%26c%3D2", article_of(page, None)),
+            ("org-protocol://kindle-pult?url=https%3A%2F%2Fexample.com%2Fa%3Fb%3D1%26c%3D2", article_of(page, None)),
+            ("org-protocol:/kindle-pult:/https%3A%2F%2Fexample.com%2Fa%3Fb%3D1%26c%3D2", article_of(page, None)),
+            ("org-protocol://kindle-pult://https%3A%2F%2Fexample.com%2Fa%3Fb%3D1%26c%3D2", article_of(page, None)),
+            ("kindle-pult://open?file=%2Fhome%2Fme%2Fmy%20book.epub", Invocation {
+                profile: None,
+                requests: vec![Request::Open(PathBuf::from("/home/me/my book.epub"))],
+            }),
+        ];
+
+        for (uri, invocation) in cases.iter() {
+            assert!(is_pult_uri(uri), "{}", uri);
+            assert_eq!(parse(uri).unwrap(), *invocation, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn refuses_unsafe_or_broken_links() {
+        let uris = [
+            "kindle-pult://send",
+            "kindle-pult://send?url=file%3A%2F%2F%2Fetc%2Fpasswd",
+            "kindle-pult://send?url=javascript%3Aalert(1)",
+            "kindle-pult://open?file=book.epub",
+            "kindle-pult://open?file=..%2Fbook.epub",
+            "kindle-pult://open",
+            "kindle-pult://delete?file=%2Ftmp%2Fa",
+            "org-protocol://capture?url=https%3A%2F%2Fexample.com",
+            "org-protocol:/kindle-pult:/",
+            "https://example.com/",
+        ];
+
+        for uri in uris.iter() {
+            assert!(parse(uri).is_err(), "{}", uri);
+        }
+        // Selected files still wait for the Send button
+        let invocation = parse("kindle-pult://open?file=%2Ftmp%2Fbook.epub").unwrap();
+        assert!(invocation.requests.iter().all(|request| matches!(request, Request::Open(_))));
+    }
+}
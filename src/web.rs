@@ -1,239 +1,457 @@
-use std::io;
+use std::io::{self, Read, Write};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::cell::Cell;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use tempfile::Builder;
 
-extern crate image;
-use image::io::Reader as ImageReader;
-
 extern crate soup;
 use soup::prelude::*;
 
 extern crate url;
-use url::{Url, ParseError};
+use url::Url;
+
+use html5ever::rcdom::{Handle, Node, NodeData};
 
 extern crate epub_builder;
-use epub_builder::{EpubBuilder, ZipLibrary, EpubContent, ReferenceType, TocElement};
+use epub_builder::{EpubBuilder, ZipLibrary, EpubContent, ReferenceType};
 
 extern crate serde;
 extern crate serde_json;
 use serde::{Deserialize};
 
-use crate::cmd::{ReadabiliPyCmd, ReadabiliPyParser};
-
-mod errors {
-    error_chain! {
-         foreign_links {
-             Io(std::io::Error);
-             HttpRequest(reqwest::Error);
-             EpubBuilding(epub_builder::Error);
-             ImageReading(image::ImageError);
-         }
-    }
-}
+use crate::config;
+use crate::download::{Downloader, DLFileType, Task};
+use crate::extract::{self, Page};
+use crate::images::{self, Device};
+use crate::metadata;
+use crate::naming::{self, NameFields};
+use crate::rules::{self, SiteRule};
+use crate::xhtml;
+use crate::errors::*;
 
-use errors::*;
+// Pages of a paginated article followed at most
+const MAX_PAGES: usize = 20;
 
-#[derive(Copy, Clone)]
-enum DLFileType {
-    Text,
-    Image,
-}
+// Written by epub-builder, see `complete_package`
+const PACKAGE_DOCUMENT: &str = "OEBPS/content.opf";
+const NCX_DOCUMENT: &str = "OEBPS/toc.ncx";
 
-struct Downloader {
-    path: PathBuf,  // Path where all file are collected;
-    file_type: Cell<DLFileType>,  // Mutate type with `.set` and `.get` Cell methods
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Article {
+    pub title: Option<String>,  // The article title
+    pub byline: Option<String>,  // Author information
+    pub date: Option<String>,
+    pub content: Option<String>,
+    pub plain_content: Option<String>,  // plain content of the article, preserving the HTML structure
+    pub language: Option<String>,  // ISO 639-1 code, e.g. "en"
+    pub site_name: Option<String>,
+    pub url: Option<String>,  // Canonical address
+    pub excerpt: Option<String>,  // Short description
+    #[serde(default)]
+    pub keywords: Vec<String>,  // Tags or subjects
 }
 
-impl Downloader {
-    fn new(path: PathBuf, file_type: DLFileType) -> Self {
-        Self {
-            path,
-            file_type: Cell::new(file_type),
-        }
-    }  // new_for_path
-
-    fn download_from(&self, target: Url) -> Result<String> {
-        // Make HTTP request for target file
-        let mut response = reqwest::blocking::get(target.as_str())?; // TODO: use non-blocking async
-
-        // Choosing filename
-        let filename = response
-            .url()
-            .path_segments()
-            .and_then(|segments| segments.last())
-            .and_then(|name| if name.is_empty() { None } else { Some(name) })
-            .unwrap_or("tmp.bin");
-
-        println!("file to download: '{:?}'", filename);
-
-        // Locate destination
-        let local_abs_path = self.path.join(filename);
-        println!("will be located under: '{:?}'", local_abs_path);
-        let mut destination = fs::File::create(local_abs_path.clone())?;
-
-        // Copy file in destination
-        match self.file_type.get() {
-            DLFileType::Text => {
-                let html_string = response.text()?;
-                io::copy(&mut html_string.as_bytes(), &mut destination)
-                    .expect("Failed to copy HTML file to destination");
-            },  // if HTML
-            DLFileType::Image => {
-                io::copy(&mut response, &mut destination)
-                    .expect("Failed to copy image to destination");
-            }  // else if Image
-        }  // match file type
-
-        Ok(local_abs_path.into_os_string().into_string().unwrap())
+impl Article {
+    /// Download `target`, extract its article and package it as EPUB in the output folder
+    /// of `cfg`, named after its template (see `naming::file_stem`). Returns the path of the written EPUB.
+    /// Downloads stop with a `Cancelled` error once `task` is cancelled.
+    pub fn epub_from_url(target: String, cfg: &HashMap<String, String>, task: &Task) -> Result<PathBuf> {
+        Article::epub_from(target, None, cfg, task)
     }
-}
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Article {
-    title: Option<String>,  // The article title
-    byline: Option<String>,  // Author information
-    date: Option<String>,
-    content: Option<String>,
-    plain_content: Option<String>,  // plain content of the article, preserving the HTML structure
-}
+    /// Same as `epub_from_url`, with the page already at hand, e.g. as rendered by a browser.
+    /// Only images are downloaded.
+    pub fn epub_from_html(target: String, html: String, cfg: &HashMap<String, String>, task: &Task) -> Result<PathBuf> {
+        Article::epub_from(target, Some(html), cfg, task)
+    }
 
-impl Article {
-    pub fn epub_from_url(target: String) -> Result<()> {
+    fn epub_from(target: String, html: Option<String>, cfg: &HashMap<String, String>, task: &Task) -> Result<PathBuf> {
         // Parse target URL
-        let target_url = Url::parse(&target);
-
-        // Check target URL validity
-        match target_url {
-            Ok(url) => { println!("{}", url) },
-            Err(e) => {
-                println!("Error {}, return.", e);
-                return Ok(())  // TODO: Implement Error InvalidURL
-            }
-        };
+        let target_url = Url::parse(&target)
+            .chain_err(|| ErrorKind::InvalidUrl(target.clone()))?;
+        info!("Downloading article {}", target_url);
 
         // Make temp dir
         let tmp_dir = Builder::new().prefix("kindle-pult_").tempdir()?;
         // Persist the tempdir and return PathBuf
         let tmp_dir_path = tmp_dir.into_path();
 
-        // Set up downloader for HTML files
-        let downloader = Downloader::new(tmp_dir_path.clone(), DLFileType::Text);
-        let target_url = Url::parse(&target);
-        let local_abs_path_string = downloader.download_from(target_url.unwrap());
+        let result = Article::epub_in_dir(&target_url, html, &tmp_dir_path, cfg, task);
 
-        // Purify HTML
-        let purifier = ReadabiliPyCmd::new(ReadabiliPyParser::Mozilla);  // Select parser
-
-        let outfile_path = tmp_dir_path.join("article.json");  // TODO: use fname
-        let outfile_path_string = outfile_path.clone().into_os_string().into_string().unwrap();
-
-        // Generate json file with ReadabiliPy
-        // TODO: print feedback to GUI
-        purifier.json_from_file(local_abs_path_string.unwrap(), outfile_path_string);
-
-        // Read Json, deserialize and print Rust data structure.
-        // TODO: print article info to GUI
-        let json_file = fs::File::open(outfile_path).expect("file not found");
-        let article: Article = serde_json::from_reader(json_file).expect("error reading json");
-
-        // Get absolute image urls
-        let image_urls = match article.clone().content {
-            Some(content) => {
-                let mut urls = Vec::new();
-                let soup = Soup::new(&content);
-
-                for img in soup.tag("img").find_all() {
-                    let image_url = img.get("src").expect("Couldn't find `src` attribute");
-
-                    // Make sure URL is absolute and add it to urls vector;
-                    match Url::parse(&image_url) {
-                        Ok(url) => {
-                            urls.push(url);
-                        },  // Already absolute, send to vector
-                        Err(e) => {
-                            match e {
-                                ParseError::RelativeUrlWithoutBase => {
-                                    println!("Relative URL: {}", &image_url);
-                                    let target_url = Url::parse(&target);  // Second parsing
-                                    let absolute_url = target_url.unwrap().join(&image_url)
-                                        .expect("Can't make absolute URL of image");
-
-                                    println!("absolute URL: {}", &absolute_url);
-                                    urls.push(absolute_url);
-                                },  // Relative URL error
-                                _ => {
-                                    println!("errore: {}", e);
-                                    return Ok(())
-                                }  // Unknown error
-                            };  // match error
-                        }  // if error
-                    }  // match url parse
-                };
-
-                println!("Image URLS: {:?}", urls);
-                urls
+        // Delete the temporary directory ourselves.
+        let _ = fs::remove_dir_all(tmp_dir_path);
+
+        result
+    }
+
+    fn epub_in_dir(
+        target_url: &Url,
+        html: Option<String>,
+        tmp_dir_path: &Path,
+        cfg: &HashMap<String, String>,
+        task: &Task,
+    ) -> Result<PathBuf> {
+        let target = target_url.to_string();
+
+        // Set up downloader for HTML files
+        let downloader = Downloader::new(tmp_dir_path.to_path_buf(), DLFileType::Text, cfg, task.clone())?;
+        let local_abs_path = match html {
+            Some(html) => {
+                let local_abs_path = tmp_dir_path.join("page.html");
+                fs::write(&local_abs_path, html)?;
+                local_abs_path
             },
-            None => {
-                Vec::new()
-            } // Empty vector
+            None => downloader.download_from(target_url.clone())
+                .chain_err(|| ErrorKind::Fetching(target.clone()))?,
         };
 
-        // Download images
-        downloader.file_type.set(DLFileType::Image);
-        let mut local_abs_image_paths = Vec::new();
+        // Purify HTML
+        let rule = rules::rule_for(target_url);
+        let device = images::device_for(cfg);
+        let (mut article, mut next_page) = Article::from_page(target_url, &local_abs_path, tmp_dir_path, &rule, device, cfg)
+            .chain_err(|| ErrorKind::Extraction(target.clone()))?;
+
+        let mut epub_content = article.content.take()
+            .ok_or_else(|| Error::from(ErrorKind::Extraction(target.clone())))?;
+
+        // Paginated article, follow the site rule to the rest of it
+        let mut seen = vec![target_url.clone()];
+        while let Some(page_url) = next_page.take() {
+            if seen.contains(&page_url) || seen.len() >= MAX_PAGES {
+                break
+            }
+            seen.push(page_url.clone());
+
+            info!("Downloading page {} of the article, {}", seen.len(), page_url);
+            let page = downloader.download_from(page_url.clone())
+                .and_then(|path| Article::from_page(&page_url, &path, tmp_dir_path, &rule, device, cfg));
+            match page {
+                Ok((page, next)) => {
+                    epub_content.push_str(&page.content.unwrap_or_default());
+                    next_page = next;
+                },
+                Err(e) if downloader.is_cancelled() => return Err(e),
+                Err(e) => warn!("Skipping the rest of the article from {}: {}", page_url, e),
+            }
+        }
 
-        for url in image_urls {
-            let local_abs_path_string = downloader.download_from(url);
-            local_abs_image_paths.push(local_abs_path_string);
+        // Download images, a missing image doesn't spoil the article
+        downloader.file_type.set(DLFileType::Image);
+        let (epub_content, local_abs_image_paths) = Article::localize_images(&epub_content, target_url, &downloader, device);
+        if downloader.is_cancelled() {
+            bail!(ErrorKind::Cancelled)
         }
 
+        // What the page doesn't tell
+        let site = target_url.host_str().map(|host| host.trim_start_matches("www."));
+        article.site_name = article.site_name.take().or_else(|| site.map(String::from));
+        article.url = article.url.take().or_else(|| Some(target.clone()));
+
         // Build epub
-        // Create a new EpubBuilder using the zip library
+        let epub_title = article.title.clone().unwrap_or_else(|| "Untitled".into());
         let mut epub: Vec<u8> = vec!();
-        let mut epub_dest = fs::File::create("book.epub")?;  // TODO: use sluggified title
 
-        let epub_title = article.title.unwrap();
-        let epub_author = article.byline.unwrap();
-        let epub_content = article.content.unwrap();
+        Article::build_epub(&mut epub, &article, &epub_content, local_abs_image_paths)
+            .chain_err(|| ErrorKind::Packaging(epub_title.clone()))?;
+
+        let fields = NameFields {
+            title: article.title.as_deref(),
+            author: article.byline.as_deref(),
+            site,
+            date: article.date.as_deref().and_then(iso_date),
+        };
+        let stem = naming::file_stem(cfg.get("name_template").unwrap(), &fields);
+        let epub_path = naming::reserve(&config::output_dir(cfg), &stem, "epub")
+            .chain_err(|| ErrorKind::Packaging(epub_title.clone()))?;
+        if let Err(e) = fs::write(&epub_path, &epub) {
+            let _ = fs::remove_file(&epub_path);
+            return Err(e).chain_err(|| ErrorKind::Packaging(epub_title.clone()))
+        }
+
+        Ok(epub_path.canonicalize()?)
+    }
+
+    // Download the images of `content` under names of their own, as JPEG or PNG, and point them there;
+    // those that can't be had are replaced by their alt text, if any. With a `device` they are
+    // optimized for it. Returns the rewritten content and the downloaded files.
+    fn localize_images(
+        content: &str,
+        base_url: &Url,
+        downloader: &Downloader,
+        device: Option<&Device>,
+    ) -> (String, Vec<PathBuf>) {
+        let soup = Soup::new(content);
+        let images: Vec<Handle> = soup.tag("img").find_all().collect();
+
+        // Addresses of the images, each downloaded once
+        let mut image_urls: Vec<Option<Url>> = Vec::new();
+        let mut targets: Vec<(Url, String)> = Vec::new();
+        for img in &images {
+            let src = img.get("src").unwrap_or_default();
+            let image_url = match base_url.join(src.trim()) {
+                Ok(image_url) if !src.trim().is_empty() => Some(image_url),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Skipping image '{}': {}", src, e);
+                    None
+                },
+            };
+
+            if let Some(image_url) = &image_url {
+                if !targets.iter().any(|(url, _)| url == image_url) {
+                    targets.push((image_url.clone(), image_name(image_url, targets.len() + 1)));
+                }
+            }
+            image_urls.push(image_url);
+        }
+
+        let downloaded = downloader.download_all(targets.clone());
+
+        let mut local_names: HashMap<Url, String> = HashMap::new();
+        let mut local_abs_image_paths = Vec::new();
+        let (mut size_before, mut size_after) = (0, 0);
+
+        for ((image_url, _), local_path) in targets.into_iter().zip(downloaded) {
+            let local_path = local_path
+                .and_then(|path| images::normalize(&path))
+                .map(|path| match device {
+                    Some(device) => {
+                        size_before += file_size(&path);
+                        let path = images::optimize(&path, device).unwrap_or_else(|e| {
+                            warn!("Couldn't optimize image '{}': {}", image_url, e);
+                            path
+                        });
+                        size_after += file_size(&path);
+                        path
+                    },
+                    None => path,
+                });
+
+            match local_path {
+                Ok(path) => {
+                    if let Some(name) = path.file_name() {
+                        local_names.insert(image_url, name.to_string_lossy().into_owned());
+                    }
+                    local_abs_image_paths.push(path);
+                },
+                Err(e) => warn!("Dropping image '{}': {}", image_url, Report::from(&e).details),
+            }
+        }
+
+        for (img, image_url) in images.iter().zip(image_urls) {
+            replace_image(img, image_url.and_then(|url| local_names.get(&url)).map(String::as_str));
+        }
+
+        if let Some(device) = device {
+            info!("Images optimized for {}: {} KiB instead of {} KiB", device.name, size_after / 1024, size_before / 1024);
+        }
+
+        // Parsed as HTML, written as XHTML for the book
+        let body = soup.tag("body").find().unwrap_or_else(|| soup.get_handle());
+        (xhtml::serialize(&body), local_abs_image_paths)
+    }
+
+    // Article in the page saved at `file`, cleaned up by the site rule with images resolved
+    // for `device`, and the address of its next page
+    fn from_page(
+        url: &Url,
+        file: &Path,
+        tmp_dir_path: &Path,
+        rule: &SiteRule,
+        device: Option<&Device>,
+        cfg: &HashMap<String, String>,
+    ) -> Result<(Article, Option<Url>)> {
+        let html = rule.clean(&fs::read_to_string(file)?);
+        let html = images::resolve_sources(&html, url, device.map(|device| device.width));
+        fs::write(file, &html)?;  // External extractors read the file
+
+        let page = Page {
+            url,
+            html: &html,
+            file,
+            tmp_dir: tmp_dir_path,
+        };
+        let mut article = extract::best_article(&page, rule, cfg)?;
+        metadata::complete(&mut article, &html, url);
+        rule.fix_metadata(&mut article, &html);
+
+        Ok((article, rule.next_page(&html, url)))
+    }
+
+    fn build_epub(
+        epub: &mut Vec<u8>,
+        article: &Article,
+        epub_content: &str,
+        local_abs_image_paths: Vec<PathBuf>,
+    ) -> Result<()> {
+        let epub_title = article.title.as_deref().unwrap_or("Untitled");
 
+        // Create a new EpubBuilder using the zip library, metadata go in as they are
         let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
-        builder.metadata("author", epub_author)?;
-        builder.metadata("title", epub_title.clone())?;
-
-        for img in local_abs_image_paths {
-            // Image string path
-            let img = img.unwrap();
-            // Get filename and extenstion
-            let img_path = Path::new(&img);
-            let filename = img_path.file_name().unwrap();
-            let ext = img_path.extension().unwrap().to_str().unwrap();
-            let ext = format!("image/{}", ext);
-            // Open image as DynamicImage
-            let img_decoded = ImageReader::open(&img)?.decode()?;
-
-            // Image optimization (using Image or Photon?)
-
-            builder.add_resource(filename, img_decoded.as_bytes(), ext)?;
+        if let Some(author) = &article.byline {
+            builder.metadata("author", xhtml::escape(author))?;
+        }
+        builder.metadata("title", xhtml::escape(epub_title))?;
+        if let Some(language) = &article.language {
+            builder.metadata("lang", language.as_str())?;
+        }
+        if let Some(excerpt) = &article.excerpt {
+            builder.metadata("description", xhtml::escape(excerpt))?;
+        }
+        for keyword in &article.keywords {
+            builder.metadata("subject", xhtml::escape(keyword))?;
+        }
+
+        // Images are already JPEG or PNG, see `images::normalize`
+        for img_path in local_abs_image_paths {
+            let filename = match img_path.file_name() {
+                Some(filename) => filename,
+                None => continue,
+            };
+
+            let img_bytes = fs::read(&img_path)?;
+            builder.add_resource(filename, img_bytes.as_slice(), images::mime_type(&img_path))?;
         };
 
-        // Add title page
-        builder.add_content(EpubContent::new("title.xhtml", epub_title.clone().as_bytes())
-                     .title(epub_title)
+        // Add title page, the table of contents takes the title as markup, see `complete_package`
+        let title_page = xhtml::document(epub_title, article.language.as_deref(), &title_page(article, epub_title));
+        builder.add_content(EpubContent::new("title.xhtml", title_page.as_bytes())
+                     .title(xhtml::escape(epub_title))
                      .reftype(ReferenceType::TitlePage))?;
 
         // Add Chapter
-        builder.add_content(EpubContent::new("article.xhtml", epub_content.as_bytes()))?;
+        let chapter = xhtml::document(epub_title, article.language.as_deref(), epub_content);
+        builder.add_content(EpubContent::new("article.xhtml", chapter.as_bytes()))?;
 
-        builder.generate(&mut epub)?;
+        let mut package = Vec::new();
+        builder.generate(&mut package)?;
+        *epub = Article::complete_package(&package, article)?;
 
-        io::copy(&mut &epub[..], &mut epub_dest)
-            .expect("Failed to copy epub file to destination");
+        Ok(())
+    }
 
-        // Delete the temporary directory ourselves.
-        fs::remove_dir_all(tmp_dir_path)?;
+    // epub-builder knows neither publisher nor source, and dates the book when it's built:
+    // add them to the package document, with the publication date. It also escapes
+    // titles in the NCX table of contents only, where they'd end up escaped twice.
+    fn complete_package(epub: &[u8], article: &Article) -> Result<Vec<u8>> {
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(epub))?;
+        let mut completed = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+
+        for i in 0..archive.len() {
+            let name = archive.by_index_raw(i)?.name().to_string();
+            let complete: fn(String, &Article) -> String = match name.as_str() {
+                PACKAGE_DOCUMENT => complete_opf,
+                NCX_DOCUMENT => unescape_toc,
+                _ => {
+                    completed.raw_copy_file(archive.by_index_raw(i)?)?;
+                    continue
+                },
+            };
+
+            let mut file = archive.by_index(i)?;
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+
+            let options = zip::write::FileOptions::default().compression_method(file.compression());
+            completed.start_file(name.as_str(), options)?;
+            completed.write_all(complete(text, article).as_bytes())?;
+        }
 
-        Ok(())
+        Ok(completed.finish()?.into_inner())
+    }
+}
+
+fn complete_opf(opf: String, article: &Article) -> String {
+    let mut extra = String::new();
+    if let Some(publisher) = &article.site_name {
+        extra.push_str(&format!("  <dc:publisher>{}</dc:publisher>\n  ", xhtml::escape(publisher)));
+    }
+    if let Some(source) = &article.url {
+        extra.push_str(&format!("  <dc:source>{}</dc:source>\n  ", xhtml::escape(source)));
     }
+    let mut opf = opf.replacen("</metadata>", &format!("{}</metadata>", extra), 1);
+
+    if let Some(date) = article.date.as_deref().and_then(iso_date) {
+        if let (Some(start), Some(end)) = (opf.find("<dc:date>"), opf.find("</dc:date>")) {
+            opf.replace_range(start + "<dc:date>".len()..end, date);
+        }
+    }
+
+    opf
+}
+
+fn unescape_toc(ncx: String, article: &Article) -> String {
+    let title = xhtml::escape(article.title.as_deref().unwrap_or("Untitled").trim());
+    ncx.replace(&format!("<text>{}</text>", xhtml::escape(&title)), &format!("<text>{}</text>", title))
+}
+
+// Body of the title page: title, author and site
+fn title_page(article: &Article, title: &str) -> String {
+    let mut body = format!("<h1>{}</h1>", xhtml::escape(title));
+    for line in article.byline.iter().chain(&article.site_name) {
+        body.push_str(&format!("\n<p>{}</p>", xhtml::escape(line)));
+    }
+    body
+}
+
+// Name of the `n`th image of an article, keeping the extension of `url` if it has one
+fn image_name(url: &Url, n: usize) -> String {
+    let ext = url.path_segments()
+        .and_then(|segments| segments.last())
+        .and_then(|name| Path::new(name).extension())
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .filter(|ext| !ext.is_empty() && ext.len() <= 4 && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    match ext {
+        Some(ext) => format!("image-{}.{}", n, ext),
+        None => format!("image-{}", n),
+    }
+}
+
+// Point `img` to `local_name`, or replace it with its alt text if it has none.
+// Alternative sources would be remote, they go.
+fn replace_image(img: &Handle, local_name: Option<&str>) {
+    if let Some(local_name) = local_name {
+        if let NodeData::Element { attrs, .. } = &img.data {
+            let mut attrs = attrs.borrow_mut();
+            attrs.retain(|attr| !["srcset", "sizes"].contains(&&*attr.name.local));
+            for attr in attrs.iter_mut().filter(|attr| &*attr.name.local == "src") {
+                attr.value = local_name.into();
+            }
+        }
+        return
+    }
+
+    let parent = match img.parent() {
+        Some(parent) => parent,
+        None => return,
+    };
+    let alt = img.get("alt").unwrap_or_default();
+    let mut children = parent.children.borrow_mut();
+    let position = match children.iter().position(|child| Rc::ptr_eq(child, img)) {
+        Some(position) => position,
+        None => return,
+    };
+
+    if alt.trim().is_empty() {
+        children.remove(position);
+    } else {
+        children[position] = Rc::new(Node {
+            parent: Cell::new(Some(Rc::downgrade(&parent))),
+            children: RefCell::new(Vec::new()),
+            data: NodeData::Text { contents: RefCell::new(alt.trim().into()) },
+        });
+    }
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+// `YYYY-MM-DD` at the start of a date, as most sites publish it
+fn iso_date(date: &str) -> Option<&str> {
+    let day = date.get(..10)?;
+    chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d").ok().map(|_| day)
 }
@@ -0,0 +1,150 @@
+use html5ever::rcdom::{Handle, NodeData};
+
+// E-readers parse the book as XML: no HTML entities, no unclosed tags, no stray characters
+
+const XHTML_NS: &str = "http://www.w3.org/1999/xhtml";
+const SVG_NS: &str = "http://www.w3.org/2000/svg";
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+/// Elements without content, written as `<br/>`.
+pub const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// `text` safe to put in HTML or XML, outside attributes.
+/// Characters XML doesn't allow, e.g. control characters, are left out.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\u{a0}' => escaped.push_str("&#160;"),  // Visible in the source, unlike the character
+            c if is_xml_char(c) => escaped.push(c),
+            _ => (),
+        }
+    }
+    escaped
+}
+
+/// `value` safe to put in a double quoted attribute.
+pub fn escape_attr(value: &str) -> String {
+    escape(value).replace('"', "&quot;")
+}
+
+/// XHTML document around the `body` markup.
+pub fn document(title: &str, lang: Option<&str>, body: &str) -> String {
+    let lang = lang.map(|lang| format!(" xml:lang=\"{0}\" lang=\"{0}\"", escape_attr(lang))).unwrap_or_default();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <html xmlns=\"{}\"{}>\n\
+         <head>\n<title>{}</title>\n</head>\n\
+         <body>\n{}\n</body>\n\
+         </html>\n",
+        XHTML_NS, lang, escape(title), body,
+    )
+}
+
+/// Children of `node`, parsed with html5ever, as XHTML markup.
+/// Comments are left out, as are attributes that aren't valid XML names.
+pub fn serialize(node: &Handle) -> String {
+    let mut out = String::new();
+    for child in node.children.borrow().iter() {
+        write_node(child, XHTML_NS, &mut out);
+    }
+    out
+}
+
+fn write_node(node: &Handle, parent_ns: &str, out: &mut String) {
+    let (name, attrs) = match &node.data {
+        NodeData::Text { contents } => return out.push_str(&escape(&contents.borrow())),
+        NodeData::Element { name, attrs, .. } => (name, attrs),
+        NodeData::Document => return out.push_str(&serialize(node)),
+        _ => return,  // Comments, doctypes and processing instructions
+    };
+
+    let tag = &*name.local;
+    if !is_xml_name(tag) {
+        // Not representable, its content still is
+        for child in node.children.borrow().iter() {
+            write_node(child, parent_ns, out);
+        }
+        return
+    }
+
+    out.push('<');
+    out.push_str(tag);
+    // SVG and MathML need their namespace declared where they start, and HTML where it resumes
+    let ns = &*name.ns;
+    if ns != parent_ns && [XHTML_NS, SVG_NS, MATHML_NS].contains(&ns) {
+        out.push_str(&format!(" xmlns=\"{}\"", ns));
+        if ns == SVG_NS {
+            out.push_str(&format!(" xmlns:xlink=\"{}\"", XLINK_NS));
+        }
+    }
+
+    for attr in attrs.borrow().iter() {
+        let local = &*attr.name.local;
+        let attr_name = match &*attr.name.ns {
+            "" => local.to_string(),
+            XLINK_NS => format!("xlink:{}", local),
+            "http://www.w3.org/XML/1998/namespace" => format!("xml:{}", local),
+            _ => continue,  // Namespace declarations, written above, or unknown prefixes
+        };
+        if local == "xmlns" || local.starts_with("xmlns:") || !is_xml_name(local) {
+            continue
+        }
+        out.push_str(&format!(" {}=\"{}\"", attr_name, escape_attr(&attr.value)));
+    }
+
+    if ns == XHTML_NS && VOID.contains(&tag) {
+        out.push_str("/>");
+        return
+    }
+    out.push('>');
+    for child in node.children.borrow().iter() {
+        write_node(child, ns, out);
+    }
+    out.push_str(&format!("</{}>", tag));
+}
+
+// Conservative: HTML parsers accept names XML doesn't, e.g. `<a 1="">` or `<a foo"bar="">`
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+// https://www.w3.org/TR/xml/#charsets
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{d7ff}' | '\u{e000}'..='\u{fffd}' | '\u{10000}'..='\u{10ffff}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use soup::prelude::*;
+
+    #[test]
+    fn serializes_html_as_xml() {
+        let html = "<p>Q&amp;A&nbsp;time<br>next \u{1}line<img src=\"a.png?x=1&y=2\" alt='\"quoted\"'></p>\
+            <!-- comment --><hr><p 1st=\"no\" class=\"ok\" xmlns=\"urn:other\">Fine</p>\
+            <svg viewBox=\"0 0 10 10\"><use xlink:href=\"#dot\"/><foreignObject><p>Inside<br></p></foreignObject></svg>\
+            <math><mi>x</mi></math><table><td>cell</table>";
+        let soup = Soup::new(html);
+        let body = soup.tag("body").find().unwrap();
+        let markup = serialize(&body);
+
+        let page = document("Q&A", Some("en"), &markup);
+        if let Err(e) = roxmltree::Document::parse(&page) {
+            panic!("{}\n{}", e, page);
+        }
+        assert!(markup.contains("Q&amp;A&#160;time<br/>next line<img src=\"a.png?x=1&amp;y=2\" alt=\"&quot;quoted&quot;\"/>"), "{}", markup);
+        assert!(markup.contains("<p class=\"ok\">Fine</p>"), "{}", markup);
+        assert!(markup.contains("<svg xmlns=\"http://www.w3.org/2000/svg\""), "{}", markup);
+        assert!(markup.contains("<p xmlns=\"http://www.w3.org/1999/xhtml\">Inside<br/></p>"), "{}", markup);
+        assert!(!markup.contains("comment"), "{}", markup);
+    }
+}